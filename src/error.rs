@@ -2,6 +2,7 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use crate::lex::Span;
 use thiserror::Error;
 
 // std result alias
@@ -19,37 +20,119 @@ pub enum Error {
     EvaluatorError(#[from] EvaluatorError),
     #[error("{0}")]
     UtilsError(#[from] UtilsError),
+    #[error("{1} (at {0})")]
+    Located(Span, Box<Error>),
 }
 
-#[derive(Debug, Error)]
+impl Error {
+    /// Attaches the span of the expression that caused the error, unless the
+    /// error already points at a (more specific) location
+    pub fn at(self, span: Span) -> Error {
+        match self {
+            Error::Located(_, _) => self,
+            e => Error::Located(span, Box::new(e)),
+        }
+    }
+
+    /// Source location of the error, if known
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::Located(span, _) => Some(*span),
+            Error::LexerError(e) => e.span(),
+            Error::ParserError(e) => e.span(),
+            _ => None,
+        }
+    }
+
+    /// The error without its location
+    pub fn without_location(&self) -> &Error {
+        match self {
+            Error::Located(_, e) => e.without_location(),
+            e => e,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
 pub enum LexerError {
-    #[error(
-        "Lexer Error: '<' is forbidden in Identifiers, Keywords and Variables (Syntax Error). \n 
-    Additional information: No LT (<=) supported yet"
-    )]
-    ForbiddenCharLEQ,
-    #[error("Lexer Error: no valid Character found")]
-    ForbiddenChar,
-    #[error("Lexer Error: Comment started but does not end")]
-    CommentError,
-    #[error("Lexer Error: Identifiers are not allowed to start with a number")]
-    IdentifierError,
-    #[error("Lexer Error: unexpected EOF")]
-    EOFError,
+    #[error("Lexer Error: no valid Character found, got {0:?} at {1}")]
+    ForbiddenChar(char, Span),
+    #[error("Lexer Error: Comment started at {0} but does not end")]
+    CommentError(Span),
+    #[error("Lexer Error: String literal started at {0} but does not end")]
+    StringError(Span),
+    #[error("Lexer Error: invalid character literal at {0}")]
+    CharError(Span),
+    #[error("Lexer Error: invalid escape sequence {0:?} at {1}")]
+    EscapeError(String, Span),
+    #[error("Lexer Error: invalid number literal at {0}")]
+    NumberError(Span),
+    #[error("Lexer Error: integer literal {0} exceeds the range of integers at {1}")]
+    IntegerOverflow(String, Span),
+    #[error("Lexer Error: Identifiers are not allowed to start with a number ({0})")]
+    IdentifierError(Span),
+    #[error("Lexer Error: unexpected EOF at {0}")]
+    EOFError(Span),
     #[error("Lexer Error (take_while): No Matches for Identifier")]
     NoMatches,
 }
 
+impl LexerError {
+    /// Position of the offending input, if the error has one
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            LexerError::ForbiddenChar(_, span)
+            | LexerError::CommentError(span)
+            | LexerError::StringError(span)
+            | LexerError::CharError(span)
+            | LexerError::EscapeError(_, span)
+            | LexerError::NumberError(span)
+            | LexerError::IntegerOverflow(_, span)
+            | LexerError::IdentifierError(span)
+            | LexerError::EOFError(span) => Some(*span),
+            LexerError::NoMatches => None,
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ParserError {
-    #[error("Parser Error: Type Error")]
-    TypeError,
-    #[error("Parser Error: pexp parse error \n {0}")]
-    PexpError(String),
-    #[error("Verify failed: No token")]
-    NoToken,
-    #[error("Verify failed: wrong token")]
-    WrongToken,
+    #[error("Parser Error: expected {expected} but found {found}{}{}", fmt_span(.span), fmt_hint(.hint))]
+    WrongToken {
+        expected: String,
+        found: String,
+        span: Option<Span>,
+        hint: Option<String>,
+    },
+    #[error("Parser Error: expected {expected} but reached the end of input{}", fmt_hint(.hint))]
+    NoToken {
+        expected: String,
+        hint: Option<String>,
+    },
+}
+
+impl ParserError {
+    /// Position of the offending token, if known
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ParserError::WrongToken { span, .. } => *span,
+            ParserError::NoToken { .. } => None,
+        }
+    }
+}
+
+fn fmt_span(span: &Option<Span>) -> String {
+    match span {
+        Some(span) => format!(" at {}", span),
+        None => String::new(),
+    }
+}
+
+fn fmt_hint(hint: &Option<String>) -> String {
+    match hint {
+        Some(hint) => format!(" (hint: {})", hint),
+        None => String::new(),
+    }
 }
 
 #[derive(Debug, Error)]
@@ -68,12 +151,39 @@ pub enum TypeCheckError {
     UnequalIfTypes,
     #[error("Typecheck Error: bool expected for if but got {0}")]
     WrongIfType(String),
-    #[error("Typecheck Error: fun has missing type")]
-    MissingFunctionType,
-    #[error("Typecheck Error: missing types for let rec")]
-    MissingType,
     #[error("Typecheck Error: declared type of let rec not matched")]
     NoTypeMatch,
+    #[error("Typecheck Error: a value would have an infinite type")]
+    InfiniteType,
+    #[error("Typecheck Error: elements of the list have different types")]
+    UnequalListTypes,
+    #[error("Typecheck Error: types of the cases of match are not equal")]
+    UnequalMatchTypes,
+    #[error("Typecheck Error: pattern does not match a value of type {0}")]
+    PatternMismatch(String),
+    #[error("Typecheck Error: variable {0} is bound several times in the pattern")]
+    RepeatedVariable(String),
+    #[error("Typecheck Error: expression has type {0} but is annotated with type {1}")]
+    AnnotationMismatch(String, String),
+    #[error("Typecheck Error: type {0} is not declared")]
+    UnboundType(String),
+    #[error("Typecheck Error: constructor {0} is not declared")]
+    UnboundConstructor(String),
+    #[error("Typecheck Error: type {0} is already declared")]
+    RedeclaredType(String),
+    #[error("Typecheck Error: constructor {0} is already declared")]
+    RedeclaredConstructor(String),
+    #[error("Typecheck Error: constructor {0} is applied to the wrong number of arguments")]
+    ConstructorArity(String),
+    #[error("Typecheck Error: argument of constructor {0} has the wrong type")]
+    ConstructorArgument(String),
+}
+
+/// Problem found by the typechecker that does not stop the program
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum Warning {
+    #[error("Warning: this expression should have type unit but has type {found}{}", fmt_span(.span))]
+    NonUnitStatement { found: String, span: Option<Span> },
 }
 
 #[derive(Debug, Error)]
@@ -86,6 +196,10 @@ pub enum EvaluatorError {
     MissingFunction,
     #[error("Evaluate Error: bool expected for if but got {0}")]
     WrongIfType(String),
+    #[error("Evaluate Error: Match_failure, no pattern matches the value {0}")]
+    MatchFailure(String),
+    #[error("Evaluate Error: Division_by_zero")]
+    DivisionByZero,
 }
 
 #[derive(Debug, Error)]
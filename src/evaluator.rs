@@ -5,68 +5,461 @@
 #![allow(non_camel_case_types)]
 
 use crate::error::{EvaluatorError, Result, TypeCheckError};
-use crate::parse::{con, exp, operator, var};
+use crate::lex::Span;
+use crate::parse::{con, exp, operator, pattern, phrase, unary, var};
+use std::cmp::Ordering;
 use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::rc::Rc;
 
 #[derive(Debug, Clone)]
 pub enum value {
     BoolValue(bool),
     IntValue(isize),
-    Closure(var, exp, BTreeMap<var, Box<value>>),
-    Rclosure(var, var, exp, BTreeMap<var, Box<value>>),
+    FloatValue(f64),
+    StringValue(String),
+    CharValue(char),
+    UnitValue,
+    Closure(var, Rc<exp>, BTreeMap<var, Box<value>>),
+    Rclosure(var, var, Rc<exp>, BTreeMap<var, Box<value>>),
+    /// function `i` of mutually recursive functions `(f, x, body)`
+    Mrclosure(Rc<[(var, var, Rc<exp>)]>, usize, BTreeMap<var, Box<value>>),
+    Builtin(var),
+    Tuple(Vec<value>),
+    List(Vec<value>),
+    /// constructor with its position among the constant or among the
+    /// non-constant constructors of its `type` declaration, and its argument.
+    /// Constructors are bound in the environment as `Variant(i, c, None)`.
+    Variant(usize, String, Option<Box<value>>),
+}
+
+impl Display for value {
+    /// OCaml notation, e.g. `[1; 2]`, `(1., "s")` or `<fun>`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            value::BoolValue(b) => write!(f, "{}", b),
+            value::IntValue(n) => write!(f, "{}", n),
+            value::FloatValue(x) if x.is_nan() => write!(f, "nan"),
+            value::FloatValue(x) if x.is_infinite() && *x > 0.0 => write!(f, "infinity"),
+            value::FloatValue(x) if x.is_infinite() => write!(f, "neg_infinity"),
+            value::FloatValue(x) if x.fract() == 0.0 && x.abs() < 1e16 => write!(f, "{}.", x),
+            value::FloatValue(x) => write!(f, "{}", x),
+            value::StringValue(s) => write!(f, "{:?}", s),
+            value::CharValue(c) => write!(f, "{:?}", c),
+            value::UnitValue => write!(f, "()"),
+            value::Closure(..) | value::Rclosure(..) | value::Mrclosure(..) | value::Builtin(_) => {
+                write!(f, "<fun>")
+            }
+            value::Tuple(vs) => {
+                let vs: Vec<String> = vs.iter().map(|v| v.to_string()).collect();
+                write!(f, "({})", vs.join(", "))
+            }
+            value::List(vs) => {
+                let vs: Vec<String> = vs.iter().map(|v| v.to_string()).collect();
+                write!(f, "[{}]", vs.join("; "))
+            }
+            value::Variant(_, c, None) => write!(f, "{}", c),
+            value::Variant(_, c, Some(v)) => match **v {
+                value::Variant(_, _, Some(_)) => write!(f, "{} ({})", c, v),
+                value::IntValue(n) if n < 0 => write!(f, "{} ({})", c, v),
+                value::FloatValue(x) if x < 0.0 => write!(f, "{} ({})", c, v),
+                _ => write!(f, "{} {}", c, v),
+            },
+        }
+    }
 }
 
 fn eval_operator(o: operator, v1: value, v2: value) -> Result<value> {
     match (o, v1, v2) {
-        (operator::Add, value::IntValue(x), value::IntValue(y)) => Ok(value::IntValue(x + y)),
-        (operator::Sub, value::IntValue(x), value::IntValue(y)) => Ok(value::IntValue(x - y)),
-        (operator::Mul, value::IntValue(x), value::IntValue(y)) => Ok(value::IntValue(x * y)),
-        (operator::Leq, value::IntValue(x), value::IntValue(y)) => Ok(value::BoolValue(x <= y)),
-        (_, _, _) => Err(EvaluatorError::ArgumentError)?,
+        (operator::Add, value::IntValue(x), value::IntValue(y)) => {
+            Ok(value::IntValue(x.wrapping_add(y)))
+        }
+        (operator::Sub, value::IntValue(x), value::IntValue(y)) => {
+            Ok(value::IntValue(x.wrapping_sub(y)))
+        }
+        (operator::Mul, value::IntValue(x), value::IntValue(y)) => {
+            Ok(value::IntValue(x.wrapping_mul(y)))
+        }
+        (operator::Div, value::IntValue(_), value::IntValue(0)) => {
+            Err(EvaluatorError::DivisionByZero)?
+        }
+        (operator::Div, value::IntValue(x), value::IntValue(y)) => {
+            Ok(value::IntValue(x.wrapping_div(y)))
+        }
+        (operator::Concat, value::StringValue(x), value::StringValue(y)) => {
+            Ok(value::StringValue(x + &y))
+        }
+        (operator::FAdd, value::FloatValue(x), value::FloatValue(y)) => {
+            Ok(value::FloatValue(x + y))
+        }
+        (operator::FSub, value::FloatValue(x), value::FloatValue(y)) => {
+            Ok(value::FloatValue(x - y))
+        }
+        (operator::FMul, value::FloatValue(x), value::FloatValue(y)) => {
+            Ok(value::FloatValue(x * y))
+        }
+        (operator::FDiv, value::FloatValue(x), value::FloatValue(y)) => {
+            Ok(value::FloatValue(x / y))
+        }
+        (operator::Cons, v, value::List(mut vs)) => {
+            vs.insert(0, v);
+            Ok(value::List(vs))
+        }
+        (operator::Append, value::List(mut xs), value::List(ys)) => {
+            xs.extend(ys);
+            Ok(value::List(xs))
+        }
+        (o, v1, v2) => {
+            let ord = compare_values(&v1, &v2)?;
+            let b = match o {
+                operator::Eq => ord == Some(Ordering::Equal),
+                operator::Neq => ord != Some(Ordering::Equal),
+                operator::Lt => ord == Some(Ordering::Less),
+                operator::Gt => ord == Some(Ordering::Greater),
+                operator::Leq => matches!(ord, Some(Ordering::Less | Ordering::Equal)),
+                operator::Geq => matches!(ord, Some(Ordering::Greater | Ordering::Equal)),
+                _ => Err(EvaluatorError::ArgumentError)?,
+            };
+            Ok(value::BoolValue(b))
+        }
+    }
+}
+
+/// Structural comparison of two values of the same type, `None` if they are
+/// unordered (a float `nan` is involved)
+fn compare_values(v1: &value, v2: &value) -> Result<Option<Ordering>> {
+    match (v1, v2) {
+        (value::BoolValue(x), value::BoolValue(y)) => Ok(Some(x.cmp(y))),
+        (value::IntValue(x), value::IntValue(y)) => Ok(Some(x.cmp(y))),
+        (value::FloatValue(x), value::FloatValue(y)) => Ok(x.partial_cmp(y)),
+        (value::StringValue(x), value::StringValue(y)) => Ok(Some(x.cmp(y))),
+        (value::CharValue(x), value::CharValue(y)) => Ok(Some(x.cmp(y))),
+        (value::UnitValue, value::UnitValue) => Ok(Some(Ordering::Equal)),
+        (value::Tuple(xs), value::Tuple(ys)) | (value::List(xs), value::List(ys)) => {
+            for (x, y) in xs.iter().zip(ys.iter()) {
+                match compare_values(x, y)? {
+                    Some(Ordering::Equal) => {}
+                    ord => return Ok(ord),
+                }
+            }
+            Ok(Some(xs.len().cmp(&ys.len())))
+        }
+        // like in OCaml, constant constructors come before the others, and
+        // both are ordered like in their declaration. Only the same
+        // constructor is equal, not one at the same position of another type.
+        (value::Variant(i, c1, x), value::Variant(j, c2, y)) => match (x, y) {
+            (None, Some(_)) => Ok(Some(Ordering::Less)),
+            (Some(_), None) => Ok(Some(Ordering::Greater)),
+            (Some(x), Some(y)) if c1 == c2 => compare_values(x, y),
+            _ => Ok(Some(i.cmp(j).then_with(|| c1.cmp(c2)))),
+        },
+        (_, _) => Err(EvaluatorError::ArgumentError)?,
+    }
+}
+
+/// Predefined functions, used when a variable is not bound
+fn builtin(x: &str) -> Option<value> {
+    match x {
+        "float_of_int" | "int_of_float" | "fst" | "snd" | "ignore" => {
+            Some(value::Builtin(x.to_string()))
+        }
+        _ => None,
+    }
+}
+
+fn eval_builtin(f: &str, v: value) -> Result<value> {
+    match (f, v) {
+        ("float_of_int", value::IntValue(n)) => Ok(value::FloatValue(n as f64)),
+        ("int_of_float", value::FloatValue(x)) => Ok(value::IntValue(x as isize)),
+        ("fst", value::Tuple(mut vs)) if vs.len() == 2 => Ok(vs.swap_remove(0)),
+        ("snd", value::Tuple(mut vs)) if vs.len() == 2 => Ok(vs.swap_remove(1)),
+        ("ignore", _) => Ok(value::UnitValue),
+        (_, _) => Err(EvaluatorError::ArgumentError)?,
     }
 }
 
-fn eval_fun(v1: value, v2: value) -> Result<value> {
+/// Whether `v` matches `p`, collecting the values of the variables of `p`
+fn matches(p: &pattern, v: &value, bindings: &mut Vec<(String, value)>) -> bool {
+    match (p, v) {
+        (pattern::Var(x), v) => {
+            bindings.push((x.to_string(), v.clone()));
+            true
+        }
+        (pattern::Wildcard, _) => true,
+        (pattern::Con(c), v) => match (c, v) {
+            (con::Bcon(x), value::BoolValue(y)) => x == y,
+            (con::Icon(x), value::IntValue(y)) => x == y,
+            (con::Fcon(x), value::FloatValue(y)) => x == y,
+            (con::Scon(x), value::StringValue(y)) => x == y,
+            (con::Ccon(x), value::CharValue(y)) => x == y,
+            (con::Ucon, value::UnitValue) => true,
+            (_, _) => false,
+        },
+        (pattern::Tuple(ps), value::Tuple(vs)) | (pattern::List(ps), value::List(vs)) => {
+            ps.len() == vs.len() && ps.iter().zip(vs).all(|(p, v)| matches(p, v, bindings))
+        }
+        (pattern::Cons(p1, p2), value::List(vs)) => match vs.split_first() {
+            Some((v1, v2)) => {
+                matches(p1, v1, bindings) && matches(p2, &value::List(v2.to_vec()), bindings)
+            }
+            None => false,
+        },
+        (pattern::Constr(c, p), value::Variant(_, c2, v)) if c == c2 => match (p, v) {
+            (Some(p), Some(v)) => matches(p, v, bindings),
+            (None, None) => true,
+            (_, _) => false,
+        },
+        (_, _) => false,
+    }
+}
+
+/// Adds the variables of `p` matched against `v` to `env`
+fn bind(env: &mut BTreeMap<String, Box<value>>, p: &pattern, v: value) -> Result<()> {
+    let mut bindings = vec![];
+    if !matches(p, &v, &mut bindings) {
+        Err(EvaluatorError::MatchFailure(v.to_string()))?
+    }
+    for (x, v) in bindings {
+        env.insert(x, Box::new(v));
+    }
+    Ok(())
+}
+
+/// Values of the variables in scope, by name
+type environment = BTreeMap<String, Box<value>>;
+
+/// Binds the argument (and for recursive closures the function itself) and
+/// returns the environment and body the application continues with.
+fn eval_fun(v1: value, v2: value) -> Result<(environment, Rc<exp>)> {
     match v1.clone() {
         value::Closure(x, e, mut env) => {
             env.insert(x, Box::new(v2));
-            evaluate(&mut env, e)
+            Ok((env, e))
         }
         value::Rclosure(f, x, e, mut env) => {
             env.insert(f, Box::new(v1));
             env.insert(x, Box::new(v2));
-            evaluate(&mut env, e)
+            Ok((env, e))
+        }
+        value::Mrclosure(functions, i, env) => {
+            let mut new_env = env.clone();
+            for (j, (f, _, _)) in functions.iter().enumerate() {
+                let g = value::Mrclosure(functions.clone(), j, env.clone());
+                new_env.insert(f.clone(), Box::new(g));
+            }
+            let (_, x, e) = &functions[i];
+            new_env.insert(x.clone(), Box::new(v2));
+            Ok((new_env, e.clone()))
         }
         _ => Err(EvaluatorError::MissingFunction)?,
     }
 }
 
 pub fn evaluate(env: &mut BTreeMap<String, Box<value>>, e: exp) -> Result<value> {
-    match e {
-        exp::Var(x) => match env.get(&x) {
-            Some(v) => Ok(*v.clone()),
-            None => Err(TypeCheckError::UnboundVariable(x))?,
-        },
-        exp::Con(con::Bcon(b)) => Ok(value::BoolValue(b)),
-        exp::Con(con::Icon(n)) => Ok(value::IntValue(n)),
-        exp::Oapp(o, e1, e2) => eval_operator(o, evaluate(env, *e1)?, evaluate(env, *e2)?),
-        exp::Fapp(e1, e2) => eval_fun(evaluate(env, *e1)?, evaluate(env, *e2)?),
-        exp::If(e1, e2, e3) => match evaluate(env, *e1)? {
-            value::BoolValue(b) => evaluate(env, b.then(|| *e2).unwrap_or(*e3)),
-            x => {
-                let x: String = format!("{:?}", x);
-                Err(EvaluatorError::WrongIfType(x))?
+    eval(env, &e)
+}
+
+fn eval(env: &mut BTreeMap<String, Box<value>>, e: &exp) -> Result<value> {
+    let mut span: Option<Span> = None;
+    eval_tail(env, e, &mut span).map_err(|err| match span {
+        Some(span) => err.at(span),
+        None => err,
+    })
+}
+
+/// Evaluates a phrase of a program, adding declared variables to `env`
+pub fn evaluate_phrase(env: &mut BTreeMap<String, Box<value>>, p: phrase) -> Result<value> {
+    match p {
+        phrase::Let(p, e) => {
+            let v = evaluate(env, e)?;
+            bind(env, &p, v.clone())?;
+            Ok(v)
+        }
+        phrase::Exp(e) => evaluate(env, e),
+        phrase::Type(_, constructors) => {
+            let (mut constant, mut non_constant) = (0, 0);
+            for (c, arg) in constructors {
+                let counter = match arg {
+                    Some(_) => &mut non_constant,
+                    None => &mut constant,
+                };
+                env.insert(c.clone(), Box::new(value::Variant(*counter, c, None)));
+                *counter += 1;
+            }
+            Ok(value::UnitValue)
+        }
+    }
+}
+
+/// Expressions in tail position (branches of `if`, the body of `let` and of
+/// applied functions) are evaluated in a loop instead of recursively, so
+/// tail-recursive programs run in constant stack space. `span` follows the
+/// innermost located expression, which is where errors of the loop come from.
+fn eval_tail(
+    env: &mut BTreeMap<String, Box<value>>,
+    e: &exp,
+    span: &mut Option<Span>,
+) -> Result<value> {
+    // body of the applied function the loop is in, which `e` points into
+    let mut fun_body: Rc<exp>;
+    let mut fun_env: Option<BTreeMap<String, Box<value>>> = None;
+    let mut e = e;
+
+    loop {
+        // bindings can only be added to environments owned by the loop, the
+        // caller's environment must not see them
+        let owned = fun_env.is_some();
+        let env = match fun_env {
+            Some(ref mut fun_env) => fun_env,
+            None => &mut *env,
+        };
+        e = match e {
+            exp::Var(x) => match env.get(x).map(|v| *v.clone()).or_else(|| builtin(x)) {
+                Some(v) => return Ok(v),
+                None => Err(TypeCheckError::UnboundVariable(x.clone()))?,
+            },
+            exp::Con(con::Bcon(b)) => return Ok(value::BoolValue(*b)),
+            exp::Con(con::Icon(n)) => return Ok(value::IntValue(*n)),
+            exp::Con(con::Fcon(x)) => return Ok(value::FloatValue(*x)),
+            exp::Con(con::Scon(s)) => return Ok(value::StringValue(s.clone())),
+            exp::Con(con::Ccon(c)) => return Ok(value::CharValue(*c)),
+            exp::Con(con::Ucon) => return Ok(value::UnitValue),
+            exp::Oapp(o, e1, e2) => {
+                return eval_operator(o.clone(), eval(env, e1)?, eval(env, e2)?)
+            }
+            exp::Uapp(o, e) => match (o, eval(env, e)?) {
+                (unary::Neg, value::IntValue(x)) => return Ok(value::IntValue(x.wrapping_neg())),
+                (unary::FNeg, value::FloatValue(x)) => return Ok(value::FloatValue(-x)),
+                _ => Err(EvaluatorError::ArgumentError)?,
+            },
+            exp::Fapp(e1, e2) => match eval(env, e1)? {
+                value::Builtin(f) => return eval_builtin(&f, eval(env, e2)?),
+                v1 => {
+                    let (new_env, body) = eval_fun(v1, eval(env, e2)?)?;
+                    fun_env = Some(new_env);
+                    fun_body = body;
+                    &fun_body
+                }
+            },
+            exp::If(e1, e2, e3) => match eval(env, e1)? {
+                value::BoolValue(true) => e2,
+                value::BoolValue(false) => e3,
+                x => {
+                    let x: String = format!("{:?}", x);
+                    Err(EvaluatorError::WrongIfType(x))?
+                }
+            },
+            exp::Lam(x, e) | exp::Lamty(x, _, e) => {
+                return Ok(value::Closure(x.clone(), e.clone(), env.clone()))
+            }
+            exp::Let(x, e1, e2) => {
+                let v = eval(env, e1)?;
+                if owned {
+                    env.insert(x.clone(), Box::new(v));
+                } else {
+                    let mut new_env = env.clone();
+                    new_env.insert(x.clone(), Box::new(v));
+                    fun_env = Some(new_env);
+                }
+                e2
+            }
+            exp::Loc(s, e) => {
+                *span = Some(*s);
+                e
+            }
+            exp::Letrec(f, x, e1, e2) | exp::Letrecty(f, x, _, _, e1, e2) => {
+                let v = value::Rclosure(f.clone(), x.clone(), e1.clone(), env.clone());
+                if owned {
+                    env.insert(f.clone(), Box::new(v));
+                } else {
+                    let mut new_env = env.clone();
+                    new_env.insert(f.clone(), Box::new(v));
+                    fun_env = Some(new_env);
+                }
+                e2
+            }
+            exp::Letrecand(functions, e2) => {
+                let functions: Rc<[(var, var, Rc<exp>)]> = functions
+                    .iter()
+                    .map(|(f, x, _, e)| (f.clone(), x.clone(), e.clone()))
+                    .collect();
+                let closure_env = env.clone();
+                if !owned {
+                    fun_env = Some(env.clone());
+                }
+                let env = fun_env.as_mut().unwrap();
+                for (i, (f, _, _)) in functions.iter().enumerate() {
+                    let v = value::Mrclosure(functions.clone(), i, closure_env.clone());
+                    env.insert(f.clone(), Box::new(v));
+                }
+                e2
+            }
+            exp::Tuple(es) => {
+                let mut vs = vec![];
+                for e in es {
+                    vs.push(eval(env, e)?);
+                }
+                return Ok(value::Tuple(vs));
+            }
+            exp::Match(e, cases) => {
+                let v = eval(env, e)?;
+                let mut body = None;
+                for (p, e) in cases {
+                    let mut bindings = vec![];
+                    if matches(p, &v, &mut bindings) {
+                        body = Some((e, bindings));
+                        break;
+                    }
+                }
+                match body {
+                    Some((e, bindings)) => {
+                        // the variables of the case are only visible in its body
+                        if !owned {
+                            fun_env = Some(env.clone());
+                        }
+                        let env = fun_env.as_mut().unwrap();
+                        for (x, v) in bindings {
+                            env.insert(x, Box::new(v));
+                        }
+                        e
+                    }
+                    None => Err(EvaluatorError::MatchFailure(v.to_string()))?,
+                }
+            }
+            exp::List(es) => {
+                let mut vs = vec![];
+                for e in es {
+                    vs.push(eval(env, e)?);
+                }
+                return Ok(value::List(vs));
+            }
+            exp::Letpat(p, e1, e2) => {
+                let v = eval(env, e1)?;
+                if owned {
+                    bind(env, p, v)?;
+                } else {
+                    let mut new_env = env.clone();
+                    bind(&mut new_env, p, v)?;
+                    fun_env = Some(new_env);
+                }
+                e2
+            }
+            exp::Constr(c, arg) => {
+                let i = match env.get(c).map(|v| &**v) {
+                    Some(value::Variant(i, _, None)) => *i,
+                    _ => Err(TypeCheckError::UnboundConstructor(c.clone()))?,
+                };
+                let v = match arg {
+                    Some(e) => Some(Box::new(eval(env, e)?)),
+                    None => None,
+                };
+                return Ok(value::Variant(i, c.clone(), v));
+            }
+            exp::Annot(e, _) => e,
+            exp::Seq(e1, e2) => {
+                eval(env, e1)?;
+                e2
             }
-        },
-        exp::Lam(x, e) | exp::Lamty(x, _, e) => Ok(value::Closure(x, *e, env.clone())),
-        exp::Let(x, e1, e2) => {
-            let v = evaluate(env, *e1)?;
-            env.insert(x, Box::new(v));
-            evaluate(env, *e2)
-        }
-        exp::Letrec(f, x, e1, e2) | exp::Letrecty(f, x, _, _, e1, e2) => {
-            env.insert(f.clone(), Box::new(value::Rclosure(f, x, *e1, env.clone())));
-            evaluate(env, *e2)
         }
     }
 }
@@ -2,18 +2,21 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use crate::error::{LexerError, Result};
-use crate::utils::getsubchar;
-use std::fmt::Debug;
-use std::iter::{Iterator, Peekable};
+use crate::error::{Error, LexerError, Result};
+use std::fmt::{self, Debug, Display};
+use std::iter::Iterator;
 use std::str;
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Constant {
     BCON(bool),
     ICON(isize),
+    FCON(f64),
+    SCON(String),
+    CCON(char),
 }
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Token {
     LP,
     RP,
@@ -23,7 +26,35 @@ pub enum Token {
     ADD,
     SUB,
     MUL,
+    DIV,
+    FADD,
+    FSUB,
+    FMUL,
+    FDIV,
     LEQ,
+    LT,
+    GT,
+    GEQ,
+    NEQ,
+    PEQ,
+    PNEQ,
+    AND,
+    OR,
+    CONS,
+    APPEND,
+    CONCAT,
+    PIPE,
+    APPLY,
+    ASSIGN,
+    BANG,
+    BAR,
+    SEMI,
+    SEMISEMI,
+    COMMA,
+    LB,
+    RB,
+    INFIXOP(String),
+    PREFIXOP(String),
     IF,
     THEN,
     ELSE,
@@ -31,146 +62,779 @@ pub enum Token {
     LET,
     IN,
     REC,
+    MATCH,
+    WITH,
+    BEGIN,
+    END,
+    /// `and` of `let rec ... and ...`, `&&` is `AND`
+    LETAND,
+    TYPE,
+    OF,
+    DOT,
+    UNDERSCORE,
     CON(Constant),
     VAR(String),
+    UIDENT(String),
+    DOC(String),
+    ERROR(String),
 }
 
-pub fn lex(code: &str) -> Result<Vec<Token>> {
-    let mut tokenlist: Vec<Token> = Vec::new();
-    let mut iterator = code.chars().peekable();
+impl Display for Token {
+    /// Writes the token the way it appears in source code
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Token::LP => "(",
+            Token::RP => ")",
+            Token::EQ => "=",
+            Token::COL => ":",
+            Token::ARR => "->",
+            Token::ADD => "+",
+            Token::SUB => "-",
+            Token::MUL => "*",
+            Token::DIV => "/",
+            Token::FADD => "+.",
+            Token::FSUB => "-.",
+            Token::FMUL => "*.",
+            Token::FDIV => "/.",
+            Token::LEQ => "<=",
+            Token::LT => "<",
+            Token::GT => ">",
+            Token::GEQ => ">=",
+            Token::NEQ => "<>",
+            Token::PEQ => "==",
+            Token::PNEQ => "!=",
+            Token::AND => "&&",
+            Token::OR => "||",
+            Token::CONS => "::",
+            Token::APPEND => "@",
+            Token::CONCAT => "^",
+            Token::PIPE => "|>",
+            Token::APPLY => "@@",
+            Token::ASSIGN => ":=",
+            Token::BANG => "!",
+            Token::BAR => "|",
+            Token::SEMI => ";",
+            Token::SEMISEMI => ";;",
+            Token::COMMA => ",",
+            Token::LB => "[",
+            Token::RB => "]",
+            Token::INFIXOP(s) | Token::PREFIXOP(s) | Token::VAR(s) | Token::UIDENT(s) => s,
+            Token::IF => "if",
+            Token::THEN => "then",
+            Token::ELSE => "else",
+            Token::LAM => "fun",
+            Token::LET => "let",
+            Token::IN => "in",
+            Token::REC => "rec",
+            Token::MATCH => "match",
+            Token::WITH => "with",
+            Token::BEGIN => "begin",
+            Token::END => "end",
+            Token::LETAND => "and",
+            Token::TYPE => "type",
+            Token::OF => "of",
+            Token::DOT => ".",
+            Token::UNDERSCORE => "_",
+            Token::CON(Constant::BCON(b)) => return write!(f, "{}", b),
+            Token::CON(Constant::ICON(n)) => return write!(f, "{}", n),
+            Token::CON(Constant::FCON(x)) => return write!(f, "{:?}", x),
+            Token::CON(Constant::SCON(s)) => return write!(f, "{:?}", s),
+            Token::CON(Constant::CCON(c)) => return write!(f, "{:?}", c),
+            Token::DOC(s) => return write!(f, "(**{}*)", s),
+            Token::ERROR(s) => s,
+        };
+        f.write_str(s)
+    }
+}
 
-    loop {
-        if let Some(_) = iterator.peek() {
-            lexer(&mut tokenlist, &mut iterator)?;
-            continue;
+/// Location of a piece of source code: the byte range `start..end` and the
+/// line and column (both starting at 1, columns counted in chars) of `start`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// Span from the start of `self` to the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end,
+            ..self
+        }
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+/// Character iterator over the source that keeps track of where it is.
+#[derive(Debug, Clone)]
+struct Cursor<'a> {
+    code: &'a str,
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(code: &'a str) -> Self {
+        Cursor {
+            code,
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    /// Remaining, not yet consumed source
+    fn rest(&self) -> &'a str {
+        &self.code[self.offset..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        self.rest().starts_with(s)
+    }
+
+    /// Empty span at the current position
+    fn location(&self) -> Span {
+        Span {
+            start: self.offset,
+            end: self.offset,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    /// Span from `start` up to the current position
+    fn span_from(&self, start: Span) -> Span {
+        Span {
+            end: self.offset,
+            ..start
+        }
+    }
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.offset += ch.len_utf8();
+        if ch == '\n' {
+            self.line += 1;
+            self.column = 1;
         } else {
-            break;
+            self.column += 1;
         }
+        Some(ch)
     }
+}
 
-    Ok(tokenlist)
+/// Streaming lexer: yields one token at a time, so consumers can stop early.
+/// After the first error it yields nothing more, unless it is `recovering`.
+#[derive(Debug, Clone)]
+pub struct Lexer<'a> {
+    iterator: Cursor<'a>,
+    failed: bool,
+    recover: bool,
+    errors: Vec<LexerError>,
+    /// whether the last token was `-`
+    negated: bool,
 }
 
-fn lexer<I>(tokenlist: &mut Vec<Token>, iterator: &mut Peekable<I>) -> Result<()>
-where
-    I: Iterator<Item = char> + Debug + Clone,
-{
-    while iterator.peek().unwrap().is_whitespace() {
-        drop(iterator.next())
+impl<'a> Lexer<'a> {
+    pub fn new(code: &'a str) -> Self {
+        Lexer {
+            iterator: Cursor::new(code),
+            failed: false,
+            recover: false,
+            errors: Vec::new(),
+            negated: false,
+        }
     }
 
-    if let Ok(e) = getsubchar(iterator, 2) {
-        match e.as_str() {
-            "(*" => {
-                drop(iterator.nth(2));
+    /// Instead of stopping at the first error, skip the offending input,
+    /// yield an `ERROR` token for it and keep the error for `take_errors`.
+    pub fn recovering(mut self) -> Self {
+        self.recover = true;
+        self
+    }
+
+    /// Errors skipped so far in recovering mode
+    pub fn take_errors(&mut self) -> Vec<LexerError> {
+        std::mem::take(&mut self.errors)
+    }
 
-                while getsubchar(iterator, 2)
-                    .map_err(|_| LexerError::CommentError)?
-                    .as_str()
-                    != "*)"
+    /// Moves past the input that caused `e`, which started at `start`
+    fn skip_error(&mut self, e: &LexerError, start: Span) {
+        let iterator = &mut self.iterator;
+        match e {
+            // the rest of a string with a bad escape belongs to the error as well
+            LexerError::EscapeError(_, _) if iterator.code[start.start..].starts_with('"') => {
+                while let Some(c) = iterator.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => {
+                            iterator.next();
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            LexerError::EscapeError(_, _) | LexerError::CharError(_) => {
+                while iterator
+                    .peek()
+                    .is_some_and(|c| c != '\'' && !c.is_whitespace())
                 {
                     iterator.next();
                 }
-                if iterator.nth(2).is_none() {
-                    return Ok(());
+                if iterator.peek() == Some('\'') {
+                    iterator.next();
                 }
             }
-            _ => (),
+            _ => {}
+        }
+        if iterator.offset == start.start {
+            iterator.next();
         }
     }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Result<SpannedToken>;
+
+    fn next(&mut self) -> Option<Result<SpannedToken>> {
+        if self.failed {
+            return None;
+        }
+        loop {
+            while self.iterator.peek().is_some_and(char::is_whitespace) {
+                self.iterator.next();
+            }
+            self.iterator.peek()?;
 
-    let token = match iterator.peek().unwrap() {
-        '+' => {
+            let start = self.iterator.location();
+            let result = match lexer(&mut self.iterator) {
+                // 9223372036854775808 only fits in an int as `-9223372036854775808`
+                Ok(Some(Token::CON(Constant::ICON(isize::MIN)))) if !self.negated => {
+                    let span = self.iterator.span_from(start);
+                    let literal = self.iterator.code[span.start..span.end].to_string();
+                    Err(LexerError::IntegerOverflow(literal, span).into())
+                }
+                result => result,
+            };
+            match result {
+                Ok(Some(token)) => {
+                    self.negated = token == Token::SUB;
+                    return Some(Ok(SpannedToken {
+                        token,
+                        span: self.iterator.span_from(start),
+                    }));
+                }
+                // comment
+                Ok(None) => continue,
+                Err(Error::LexerError(e)) if self.recover => {
+                    self.negated = false;
+                    self.skip_error(&e, start);
+                    self.errors.push(e);
+                    let span = self.iterator.span_from(start);
+                    let text = self.iterator.code[span.start..span.end].to_string();
+                    return Some(Ok(SpannedToken {
+                        token: Token::ERROR(text),
+                        span,
+                    }));
+                }
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+pub fn lex(code: &str) -> Result<Vec<Token>> {
+    Lexer::new(code)
+        .map(|spanned| spanned.map(|spanned| spanned.token))
+        .collect()
+}
+
+pub fn lex_spanned(code: &str) -> Result<Vec<SpannedToken>> {
+    Lexer::new(code).collect()
+}
+
+/// Lexes the whole input even if it contains errors. Invalid input becomes
+/// `ERROR` tokens, and all errors are returned alongside the tokens.
+pub fn lex_recovering(code: &str) -> (Vec<SpannedToken>, Vec<LexerError>) {
+    let mut lexer = Lexer::new(code).recovering();
+    let tokens: Vec<SpannedToken> = lexer.by_ref().flatten().collect();
+    (tokens, lexer.take_errors())
+}
+
+/// Reads the next token. Returns `None` for skipped comments.
+fn lexer(iterator: &mut Cursor) -> Result<Option<Token>> {
+    let start = iterator.location();
+    let token = match iterator.peek() {
+        Some('(') if iterator.starts_with("(*") => match tokenize_comment(iterator)? {
+            Some(doc) => Token::DOC(doc),
+            None => return Ok(None),
+        },
+        Some('(') => {
             iterator.next();
-            Token::ADD
+            Token::LP
         }
-        '*' => {
+        Some(')') => {
             iterator.next();
-            Token::MUL
+            Token::RP
         }
-        '=' => {
+        Some('[') => {
             iterator.next();
-            Token::EQ
+            Token::LB
         }
-        '(' => {
+        Some(']') => {
             iterator.next();
-            Token::LP
+            Token::RB
         }
-        ')' => {
+        Some(',') => {
             iterator.next();
-            Token::RP
+            Token::COMMA
         }
-        '<' => {
+        Some(';') => {
             iterator.next();
-            if iterator.peek() == Some(&'=') {
+            if iterator.peek() == Some(';') {
                 iterator.next();
-                Token::LEQ
+                Token::SEMISEMI
             } else {
-                return Err(LexerError::ForbiddenCharLEQ)?;
+                Token::SEMI
+            }
+        }
+        Some(':') => {
+            iterator.next();
+            match iterator.peek() {
+                Some(':') => {
+                    iterator.next();
+                    Token::CONS
+                }
+                Some('=') => {
+                    iterator.next();
+                    Token::ASSIGN
+                }
+                _ => Token::COL,
             }
         }
-        '-' => {
+        Some(c) if is_operator_start(c) => tokenize_operator(iterator, c)?,
+        Some('"') => tokenize_string(iterator)?,
+        Some('\'') => tokenize_char(iterator)?,
+        Some('{') => tokenize_quoted_string(iterator)?,
+        Some('0'..='9') => tokenize_number(iterator)?,
+        Some('.') => {
             iterator.next();
-            if iterator.peek() == Some(&'>') {
+            Token::DOT
+        }
+        Some(c) if c.is_alphabetic() || c == '_' => tokenize_identifiers(iterator)?,
+        Some(c) => Err(LexerError::ForbiddenChar(c, start))?,
+        None => Err(LexerError::EOFError(start))?,
+    };
+
+    Ok(Some(token))
+}
+
+fn is_operator_start(c: char) -> bool {
+    matches!(
+        c,
+        '!' | '?'
+            | '~'
+            | '$'
+            | '&'
+            | '*'
+            | '+'
+            | '-'
+            | '/'
+            | '='
+            | '>'
+            | '@'
+            | '^'
+            | '|'
+            | '%'
+            | '<'
+            | '#'
+    )
+}
+
+fn is_operator_char(c: char) -> bool {
+    is_operator_start(c) && c != '#' || c == ':' || c == '.'
+}
+
+/// Operators are read greedily like in OCaml (`+-` is one operator). The ones
+/// with a meaning of their own get a dedicated token, everything else is an
+/// `INFIXOP` or, if it starts with `!`, `?` or `~`, a `PREFIXOP`.
+fn tokenize_operator(iterator: &mut Cursor, first: char) -> Result<Token> {
+    let start = iterator.location();
+    let rest = iterator.rest();
+    let length = 1 + rest[1..]
+        .chars()
+        .take_while(|c| is_operator_char(*c))
+        .count();
+    let op = &rest[..length];
+    iterator.nth(length - 1);
+
+    let token = match op {
+        "+" => Token::ADD,
+        "-" => Token::SUB,
+        "*" => Token::MUL,
+        "/" => Token::DIV,
+        "+." => Token::FADD,
+        "-." => Token::FSUB,
+        "*." => Token::FMUL,
+        "/." => Token::FDIV,
+        "=" => Token::EQ,
+        "<" => Token::LT,
+        ">" => Token::GT,
+        "<=" => Token::LEQ,
+        ">=" => Token::GEQ,
+        "<>" => Token::NEQ,
+        "==" => Token::PEQ,
+        "!=" => Token::PNEQ,
+        "&&" => Token::AND,
+        "||" => Token::OR,
+        "@" => Token::APPEND,
+        "^" => Token::CONCAT,
+        "|" => Token::BAR,
+        "!" => Token::BANG,
+        "|>" => Token::PIPE,
+        "@@" => Token::APPLY,
+        "->" => Token::ARR,
+        // labels (`~x`, `?x`) and `#` on its own are not supported
+        "?" | "~" | "#" => Err(LexerError::ForbiddenChar(first, start))?,
+        _ if op.starts_with(['!', '?', '~']) => Token::PREFIXOP(op.to_string()),
+        _ => Token::INFIXOP(op.to_string()),
+    };
+    Ok(token)
+}
+
+/// Skips a comment, which may contain nested comments and string literals.
+/// Doc comments `(** ... *)` are not skipped but returned with their text.
+fn tokenize_comment(iterator: &mut Cursor) -> Result<Option<String>> {
+    let start = iterator.location();
+    iterator.nth(1);
+
+    let doc =
+        iterator.starts_with("*") && !iterator.starts_with("*)") && !iterator.starts_with("**");
+    if doc {
+        iterator.next();
+    }
+    let text = iterator.rest();
+    let text_start = iterator.offset;
+
+    let mut depth = 1;
+    loop {
+        if iterator.starts_with("*)") {
+            depth -= 1;
+            if depth == 0 {
+                let text = &text[..iterator.offset - text_start];
+                iterator.nth(1);
+                return Ok(doc.then(|| text.trim().to_string()));
+            }
+            iterator.nth(1);
+        } else if iterator.starts_with("(*") {
+            depth += 1;
+            iterator.nth(1);
+        } else {
+            match iterator.next() {
+                Some('"') => loop {
+                    match iterator.next() {
+                        Some('"') => break,
+                        Some('\\') => {
+                            iterator.next();
+                        }
+                        Some(_) => {}
+                        None => Err(LexerError::CommentError(start))?,
+                    }
+                },
+                // skip char literals such as '"' so they do not open a string
+                Some('\'') => {
+                    let mut chars = iterator.rest().chars();
+                    match (chars.next(), chars.next(), chars.next()) {
+                        (Some('\\'), Some(_), Some('\'')) => {
+                            iterator.nth(2);
+                        }
+                        (Some(_), Some('\''), _) => {
+                            iterator.nth(1);
+                        }
+                        _ => {}
+                    }
+                }
+                Some(_) => {}
+                None => Err(LexerError::CommentError(start))?,
+            }
+        }
+    }
+}
+
+fn tokenize_string(iterator: &mut Cursor) -> Result<Token> {
+    let start = iterator.location();
+    iterator.next();
+
+    let mut string = String::new();
+    loop {
+        match iterator.peek() {
+            Some('"') => {
                 iterator.next();
-                Token::ARR
-            } else {
-                Token::SUB
+                return Ok(Token::CON(Constant::SCON(string)));
             }
+            Some('\\') if iterator.rest()[1..].starts_with(['\n', '\r']) => {
+                // escaped newline: skip it together with the next line's indentation
+                iterator.next();
+                while iterator
+                    .peek()
+                    .is_some_and(|c| matches!(c, '\n' | '\r' | ' ' | '\t'))
+                {
+                    iterator.next();
+                }
+            }
+            Some('\\') => string.push(tokenize_escape(iterator, true)?),
+            Some(c) => {
+                iterator.next();
+                string.push(c);
+            }
+            None => Err(LexerError::StringError(start))?,
         }
-        ':' => {
+    }
+}
+
+/// Char literal `'c'` or `'\escape'`
+fn tokenize_char(iterator: &mut Cursor) -> Result<Token> {
+    let start = iterator.location();
+    iterator.next();
+
+    let c = match iterator.peek() {
+        Some('\\') => tokenize_escape(iterator, false)?,
+        Some(c) if c != '\'' => {
             iterator.next();
-            Token::COL
+            c
         }
-        '0'..='9' => tokenize_number(iterator)?,
-        c if c.is_lowercase() => tokenize_identifiers(iterator)?,
-        _ => Err(LexerError::ForbiddenChar)?,
+        _ => Err(LexerError::CharError(start))?,
     };
+    if iterator.peek() != Some('\'') {
+        Err(LexerError::CharError(start))?
+    }
+    iterator.next();
+    Ok(Token::CON(Constant::CCON(c)))
+}
+
+/// Reads an escape sequence starting at the backslash. Decimal, hex and
+/// octal escapes denote bytes, which are mapped to the chars U+0000 to U+00FF.
+/// `\u{...}` escapes are only allowed in strings.
+fn tokenize_escape(iterator: &mut Cursor, in_string: bool) -> Result<char> {
+    let start = iterator.location();
+    iterator.next();
 
-    tokenlist.push(token);
-    Ok(())
+    let c = match iterator.peek() {
+        Some('0'..='9') => escape_digits(iterator, 10, 3),
+        Some('x') => {
+            iterator.next();
+            escape_digits(iterator, 16, 2)
+        }
+        Some('o') => {
+            iterator.next();
+            escape_digits(iterator, 8, 3)
+        }
+        Some('u') if in_string => {
+            iterator.next();
+            escape_unicode(iterator)
+        }
+        Some(c) => {
+            iterator.next();
+            match c {
+                '\\' | '"' | '\'' | ' ' => Some(c),
+                'n' => Some('\n'),
+                't' => Some('\t'),
+                'b' => Some('\u{8}'),
+                'r' => Some('\r'),
+                _ => None,
+            }
+        }
+        None => None,
+    };
+
+    match c {
+        Some(c) => Ok(c),
+        None => {
+            let span = iterator.span_from(start);
+            Err(LexerError::EscapeError(
+                iterator.code[span.start..span.end].to_string(),
+                span,
+            ))?
+        }
+    }
 }
 
-fn tokenize_number<I>(iterator: &mut Peekable<I>) -> Result<Token>
-where
-    I: Iterator<Item = char> + Debug,
-{
-    /* this should never happen
-    if let Some(n) = iterator.peek() {
-        if !n.is_numeric() {
-            unreachable!("tokenize_number: Iterator does not begin with a Number");
+/// Byte given by exactly `n` digits in `radix`
+fn escape_digits(iterator: &mut Cursor, radix: u32, n: usize) -> Option<char> {
+    let digits: String = iterator
+        .rest()
+        .chars()
+        .take(n)
+        .take_while(|c| c.is_digit(radix))
+        .collect();
+    iterator.nth(digits.len().checked_sub(1)?);
+    match u32::from_str_radix(&digits, radix) {
+        Ok(byte) if digits.len() == n && byte <= 255 => char::from_u32(byte),
+        _ => None,
+    }
+}
+
+/// Unicode scalar value `{X..X}` with one to six hex digits
+fn escape_unicode(iterator: &mut Cursor) -> Option<char> {
+    if iterator.next() != Some('{') {
+        return None;
+    }
+    let digits: String = iterator
+        .rest()
+        .chars()
+        .take_while(|c| c.is_ascii_hexdigit())
+        .collect();
+    iterator.nth(digits.len().checked_sub(1)?);
+    if digits.len() > 6 || iterator.next() != Some('}') {
+        return None;
+    }
+    u32::from_str_radix(&digits, 16)
+        .ok()
+        .and_then(char::from_u32)
+}
+
+/// Quoted string `{id|...|id}`, taken verbatim without escapes
+fn tokenize_quoted_string(iterator: &mut Cursor) -> Result<Token> {
+    let start = iterator.location();
+    let id: String = iterator.rest()[1..]
+        .chars()
+        .take_while(|c| *c == '_' || c.is_ascii_lowercase())
+        .collect();
+    if !iterator.rest()[1 + id.len()..].starts_with('|') {
+        Err(LexerError::ForbiddenChar('{', start))?
+    }
+    iterator.nth(id.len() + 1);
+
+    let end = format!("|{}}}", id);
+    match iterator.rest().find(&end) {
+        Some(length) => {
+            let string = iterator.rest()[..length].to_string();
+            for _ in string.chars().chain(end.chars()) {
+                iterator.next();
+            }
+            Ok(Token::CON(Constant::SCON(string)))
+        }
+        None => Err(LexerError::StringError(start))?,
+    }
+}
+
+/// Integer literal (decimal, or with a `0x`, `0o` or `0b` prefix), or float
+/// literal if a fraction (`3.`, `3.14`) or an exponent (`1e-3`) follows the
+/// decimal digits. Digits may be separated by `_`.
+fn tokenize_number(iterator: &mut Cursor) -> Result<Token> {
+    let start = iterator.location();
+    // digits in `radix` and underscores, starting with a digit
+    let digits = |s: &str, radix: u32| {
+        if s.starts_with(|c: char| c.is_digit(radix)) {
+            s.chars()
+                .take_while(|c| c.is_digit(radix) || *c == '_')
+                .count()
+        } else {
+            0
         }
-    }*/
+    };
 
-    let mut integer: isize = 0;
+    let literal = iterator.rest();
+    let radix = match literal.get(..2) {
+        Some("0x" | "0X") => 16,
+        Some("0o" | "0O") => 8,
+        Some("0b" | "0B") => 2,
+        _ => 10,
+    };
+    let prefix = if radix == 10 { 0 } else { 2 };
+    let mut length = prefix + digits(&literal[prefix..], radix);
+    let mut float = false;
+    if radix == 10 && literal[length..].starts_with('.') {
+        float = true;
+        length += 1;
+        length += literal[length..]
+            .chars()
+            .take_while(|c| c.is_ascii_digit() || *c == '_')
+            .count();
+    }
+    if radix == 10 && literal[length..].starts_with(['e', 'E']) {
+        let sign = literal[length + 1..].starts_with(['+', '-']) as usize;
+        let exponent = digits(&literal[length + 1 + sign..], 10);
+        if exponent > 0 {
+            float = true;
+            length += 1 + sign + exponent;
+        }
+    }
 
-    while if let Some(n) = iterator.peek() {
-        n.is_numeric()
-    } else {
-        false
-    } {
-        integer = integer * 10 + iterator.next().unwrap().to_digit(10).unwrap() as isize;
+    // like OCaml, reject literals that run into identifier characters (`0b12`, `1e`, `3x`)
+    let identchar = |c: char| c.is_alphanumeric() || c == '_' || c == '\'';
+    if length == prefix || literal[length..].starts_with(identchar) {
+        while iterator.peek().is_some_and(identchar) {
+            iterator.next();
+        }
+        Err(LexerError::NumberError(iterator.span_from(start)))?
+    }
+
+    iterator.nth(length - 1);
+    let text: String = literal[prefix..length]
+        .chars()
+        .filter(|c| *c != '_')
+        .collect();
+    if float {
+        return match text.parse::<f64>() {
+            Ok(x) => Ok(Token::CON(Constant::FCON(x))),
+            Err(_) => Err(LexerError::NumberError(iterator.span_from(start)))?,
+        };
+    }
+    match isize::from_str_radix(&text, radix) {
+        Ok(n) => Ok(Token::CON(Constant::ICON(n))),
+        // the magnitude of min_int, which `Lexer` only accepts after a `-`
+        Err(_) if u64::from_str_radix(&text, radix) == Ok(isize::MIN.unsigned_abs() as u64) => {
+            Ok(Token::CON(Constant::ICON(isize::MIN)))
+        }
+        Err(_) => Err(LexerError::IntegerOverflow(
+            literal[..length].to_string(),
+            iterator.span_from(start),
+        ))?,
     }
-    Ok(Token::CON(Constant::ICON(integer)))
 }
 
-fn tokenize_identifiers<I>(iterator: &mut Peekable<I>) -> Result<Token>
-where
-    I: Iterator<Item = char> + Debug + Clone,
-{
+fn tokenize_identifiers(iterator: &mut Cursor) -> Result<Token> {
     match iterator.peek() {
-        Some(ch) if ch.is_digit(10) => Err(LexerError::IdentifierError)?,
-        None => Err(LexerError::EOFError)?,
+        Some(ch) if ch.is_ascii_digit() => Err(LexerError::IdentifierError(iterator.location()))?,
+        None => Err(LexerError::EOFError(iterator.location()))?,
         _ => {}
     }
 
-    let code: String = iterator.clone().collect::<String>();
+    let (got, _) = take_while(iterator.rest(), |ch| {
+        ch == '_' || ch == '\'' || ch.is_alphanumeric()
+    })?;
 
-    let (got, index) = take_while(&code, |ch| ch == '_' || ch.is_alphanumeric())?;
-
-    drop(iterator.nth(index - 1));
+    iterator.nth(got.chars().count() - 1);
 
     let token: Token = match got {
+        "_" => Token::UNDERSCORE,
         "if" => Token::IF,
         "then" => Token::THEN,
         "else" => Token::ELSE,
@@ -178,8 +842,16 @@ where
         "let" => Token::LET,
         "in" => Token::IN,
         "rec" => Token::REC,
+        "match" => Token::MATCH,
+        "with" => Token::WITH,
+        "begin" => Token::BEGIN,
+        "end" => Token::END,
+        "and" => Token::LETAND,
+        "type" => Token::TYPE,
+        "of" => Token::OF,
         "false" => Token::CON(Constant::BCON(false)),
         "true" => Token::CON(Constant::BCON(true)),
+        _ if got.starts_with(char::is_uppercase) => Token::UIDENT(got.to_string()),
         _ => Token::VAR(got.to_string()),
     };
 
@@ -215,46 +887,38 @@ mod tests {
 
     use super::*;
     use crate::lex::Token::*;
-    use Constant::{BCON, ICON};
+    use Constant::{BCON, CCON, FCON, ICON, SCON};
 
     fn check_ok<S>(panicmsg: S, code: &str) -> Vec<Token>
     where
         S: AsRef<str> + Display,
     {
-        let r = if let Ok(e) = lex(&code) {
+        if let Ok(e) = lex(code) {
             e
         } else {
             panic!("{}", panicmsg)
-        };
-
-        r
+        }
     }
 
     #[test]
     fn test_number_only() {
         let mut code = "123456789";
-        let mut r = check_ok("lexer returned Err instead of Ok for \"123456789\"", &code);
+        let mut r = check_ok("lexer returned Err instead of Ok for \"123456789\"", code);
         assert_eq!(r, vec![CON(ICON(123456789))]);
 
         code = "00011122223333444555";
         r = check_ok(
             "lexer returned Err instead of Ok for \"00011122223333444555\"",
-            &code,
+            code,
         );
         assert_eq!(r, vec![CON(ICON(11122223333444555))]);
 
         code = "01010101010";
-        r = check_ok(
-            "lexer returned Err instead of Ok for \"01010101010\"",
-            &code,
-        );
+        r = check_ok("lexer returned Err instead of Ok for \"01010101010\"", code);
         assert_eq!(r, vec![CON(ICON(1010101010))]);
 
         code = "0";
-        r = check_ok(
-            "lexer returned Err instead of Ok for \"01010101010\"",
-            &code,
-        );
+        r = check_ok("lexer returned Err instead of Ok for \"01010101010\"", code);
         assert_eq!(r, vec![CON(ICON(0))]);
     }
 
@@ -263,7 +927,7 @@ mod tests {
         let mut code = "if true then false else true";
         let mut r = check_ok(
             "lexer returned Err instead of Ok for \"let rec test n = test n\"",
-            &code,
+            code,
         );
         assert_eq!(
             r,
@@ -278,7 +942,7 @@ mod tests {
         );
 
         code = "x let rec false in true fun else if xyz then abcdefg";
-        r = check_ok("lexer returned Err instead of Ok for \"x let rec false in true fun else if xyz then abcdefg\"",&code);
+        r = check_ok("lexer returned Err instead of Ok for \"x let rec false in true fun else if xyz then abcdefg\"",code);
         assert_eq!(
             r,
             vec![
@@ -300,8 +964,12 @@ mod tests {
 
     #[test]
     fn test_keywords_only() {
-        let code = "+*=()<=-:";
-        let r = check_ok("lexer returned Err instead of Ok for \"+*=()<-:\"", &code);
+        // operator characters are read greedily, so they need to be separated
+        let code = "+ * =()<= - :";
+        let r = check_ok(
+            "lexer returned Err instead of Ok for \"+ * =()<= - :\"",
+            code,
+        );
         assert_eq!(r, vec![ADD, MUL, EQ, LP, RP, LEQ, SUB, COL]);
     }
 
@@ -310,7 +978,7 @@ mod tests {
         let code = "let rec test n = test n";
         let r = check_ok(
             "lexer returned Err instead of Ok for Test, id+kw: \"let rec test n = test n\"",
-            &code,
+            code,
         );
         assert_eq!(
             r,
@@ -329,10 +997,397 @@ mod tests {
     #[test]
     #[should_panic]
     fn invalid_keywords() {
-        let code = "<";
+        let code = "`";
         let _r = check_ok(
-            "lexer returned Err and thats how it should be, kw: \"<let rec test n = test n>\"",
-            &code,
+            "lexer returned Err and thats how it should be, kw: \"`\"",
+            code,
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let r = lex_spanned("let x =\n  (* c *) 42").unwrap();
+        let spans: Vec<(usize, usize, usize, usize)> = r
+            .iter()
+            .map(|t| (t.span.start, t.span.end, t.span.line, t.span.column))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![(0, 3, 1, 1), (4, 5, 1, 5), (6, 7, 1, 7), (18, 20, 2, 11)]
+        );
+        assert_eq!(r[3].token, CON(ICON(42)));
+    }
+
+    #[test]
+    fn test_error_positions() {
+        match lex("x\n  y # z") {
+            Err(crate::error::Error::LexerError(LexerError::ForbiddenChar(c, span))) => {
+                assert_eq!(c, '#');
+                assert_eq!((span.start, span.line, span.column), (6, 2, 5));
+            }
+            r => panic!("expected ForbiddenChar, got {:?}", r),
+        }
+        match lex("1 + (* open") {
+            Err(crate::error::Error::LexerError(e @ LexerError::CommentError(_))) => {
+                assert_eq!(e.span().map(|s| (s.start, s.column)), Some((4, 5)));
+            }
+            r => panic!("expected CommentError, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_comments() {
+        let code = "1 (* a (* b *) c *) + (* \"*)\" '\"' *) 2 (* end *)";
+        let r = check_ok("lexer returned Err for nested comments", code);
+        assert_eq!(r, vec![CON(ICON(1)), ADD, CON(ICON(2))]);
+
+        let code = "(** The answer *) let x = (**) (***) 42";
+        let r = check_ok("lexer returned Err for doc comments", code);
+        assert_eq!(
+            r,
+            vec![
+                DOC("The answer".to_string()),
+                LET,
+                VAR("x".to_string()),
+                EQ,
+                CON(ICON(42))
+            ]
+        );
+
+        assert!(lex("(* a (* b *)").is_err());
+        assert!(lex("(* \"*) *)").is_err());
+    }
+
+    #[test]
+    fn test_string_and_char_literals() {
+        let code = r#""a\n\t\\\"b" "\065\x42\o103\u{1F600}" "x\
+                      y" {|raw \n "|} {id|a|}b|id} 'c' '\'' '\n' '\120'"#;
+        let r = check_ok("lexer returned Err for string and char literals", code);
+        assert_eq!(
+            r,
+            vec![
+                CON(SCON("a\n\t\\\"b".to_string())),
+                CON(SCON("ABC\u{1F600}".to_string())),
+                CON(SCON("xy".to_string())),
+                CON(SCON("raw \\n \"".to_string())),
+                CON(SCON("a|}b".to_string())),
+                CON(CCON('c')),
+                CON(CCON('\'')),
+                CON(CCON('\n')),
+                CON(CCON('x')),
+            ]
+        );
+
+        assert!(matches!(
+            lex(r#""abc"#),
+            Err(crate::error::Error::LexerError(LexerError::StringError(_)))
+        ));
+        assert!(matches!(
+            lex(r#""a\qb""#),
+            Err(crate::error::Error::LexerError(LexerError::EscapeError(e, _))) if e == "\\q"
+        ));
+        assert!(lex(r#""\256""#).is_err());
+        assert!(lex(r"'\u{41}'").is_err());
+        assert!(lex("'ab'").is_err());
+        assert!(lex("{|open").is_err());
+    }
+
+    #[test]
+    fn test_floats() {
+        let code = "2.75 1e-3 2. 5E2 7 1.5e+1 +. -. *. /. - ->";
+        let r = check_ok("lexer returned Err for float literals", code);
+        assert_eq!(
+            r,
+            vec![
+                CON(FCON(2.75)),
+                CON(FCON(1e-3)),
+                CON(FCON(2.0)),
+                CON(FCON(500.0)),
+                CON(ICON(7)),
+                CON(FCON(15.0)),
+                FADD,
+                FSUB,
+                FMUL,
+                FDIV,
+                SUB,
+                ARR
+            ]
+        );
+    }
+
+    #[test]
+    fn test_integer_literals() {
+        let code = "0x1F 0Xff 0o17 0b1010 1_000_000 12_ 1_0.5_e1_0 9223372036854775807";
+        let r = check_ok("lexer returned Err for integer literals", code);
+        assert_eq!(
+            r,
+            vec![
+                CON(ICON(31)),
+                CON(ICON(255)),
+                CON(ICON(15)),
+                CON(ICON(10)),
+                CON(ICON(1_000_000)),
+                CON(ICON(12)),
+                CON(FCON(10.5e10)),
+                CON(ICON(isize::MAX)),
+            ]
+        );
+
+        match lex("1 + 9223372036854775808") {
+            Err(crate::error::Error::LexerError(LexerError::IntegerOverflow(lit, span))) => {
+                assert_eq!(lit, "9223372036854775808");
+                assert_eq!((span.start, span.end), (4, 23));
+            }
+            r => panic!("expected IntegerOverflow, got {:?}", r),
+        }
+        // min_int can be written as a negated literal
+        assert_eq!(
+            lex("-9223372036854775808").unwrap(),
+            vec![SUB, CON(ICON(isize::MIN))]
+        );
+        assert!(lex("9223372036854775808").is_err());
+        assert!(lex("- (9223372036854775808)").is_err());
+        assert!(lex("-9223372036854775809").is_err());
+        assert!(lex("0xffffffffffffffffff").is_err());
+        for code in ["0b102", "0o8", "0x", "0x_1", "1e", "3x", "12'"] {
+            assert!(
+                matches!(
+                    lex(code),
+                    Err(crate::error::Error::LexerError(LexerError::NumberError(_)))
+                ),
+                "{} should be an invalid literal",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn test_identifier_grammar() {
+        let code = "Some x' acc'' _tmp _ List.map Leaf_2 é";
+        let r = check_ok("lexer returned Err for identifiers", code);
+        assert_eq!(
+            r,
+            vec![
+                UIDENT("Some".to_string()),
+                VAR("x'".to_string()),
+                VAR("acc''".to_string()),
+                VAR("_tmp".to_string()),
+                UNDERSCORE,
+                UIDENT("List".to_string()),
+                DOT,
+                VAR("map".to_string()),
+                UIDENT("Leaf_2".to_string()),
+                VAR("é".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_operators() {
+        let code = "< > >= <> == != && || :: @ ^ ; ;; , [ ] | / ! := |> @@ <= -> +. |||> ~- !! % $";
+        let r = check_ok("lexer returned Err for operators", code);
+        assert_eq!(
+            r,
+            vec![
+                LT,
+                GT,
+                GEQ,
+                NEQ,
+                PEQ,
+                PNEQ,
+                AND,
+                OR,
+                CONS,
+                APPEND,
+                CONCAT,
+                SEMI,
+                SEMISEMI,
+                COMMA,
+                LB,
+                RB,
+                BAR,
+                DIV,
+                BANG,
+                ASSIGN,
+                PIPE,
+                APPLY,
+                LEQ,
+                ARR,
+                FADD,
+                INFIXOP("|||>".to_string()),
+                PREFIXOP("~-".to_string()),
+                PREFIXOP("!!".to_string()),
+                INFIXOP("%".to_string()),
+                INFIXOP("$".to_string()),
+            ]
+        );
+
+        let r = check_ok("lexer returned Err for list syntax", "x::[1;2]@[]");
+        assert_eq!(
+            r,
+            vec![
+                VAR("x".to_string()),
+                CONS,
+                LB,
+                CON(ICON(1)),
+                SEMI,
+                CON(ICON(2)),
+                RB,
+                APPEND,
+                LB,
+                RB
+            ]
+        );
+        assert!(lex("~x").is_err());
+        assert!(lex("#").is_err());
+    }
+
+    #[test]
+    fn test_streaming() {
+        let mut lexer = Lexer::new("let x = 1 in ` x");
+        assert_eq!(lexer.next().map(|t| t.unwrap().token), Some(LET));
+        assert_eq!(
+            lexer.nth(1).map(|t| t.unwrap().span),
+            Some(Span {
+                start: 6,
+                end: 7,
+                line: 1,
+                column: 7
+            })
         );
+        let r: Vec<Result<SpannedToken>> = lexer.collect();
+        assert_eq!(r.len(), 3);
+        assert!(r[2].is_err());
+
+        // 175k tokens, lexed in linear time
+        let code = "let abc = 12 + abc in (* c *)\n".repeat(25_000);
+        let r = lex_spanned(&code).unwrap();
+        assert_eq!(r.len(), 175_000);
+        assert_eq!(r[174_999].span.line, 25_000);
+    }
+
+    /// Span invariants of a successful lex: tokens are non-empty, in order,
+    /// on char boundaries, and line/column agree with the byte offset.
+    fn check_spans(code: &str, tokens: &[SpannedToken]) {
+        let mut previous_end = 0;
+        for t in tokens {
+            let span = t.span;
+            assert!(previous_end <= span.start && span.start < span.end && span.end <= code.len());
+            assert!(code.is_char_boundary(span.start) && code.is_char_boundary(span.end));
+            let before = &code[..span.start];
+            let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+            assert_eq!(span.line, 1 + before.matches('\n').count());
+            assert_eq!(span.column, 1 + before[line_start..].chars().count());
+            previous_end = span.end;
+        }
+    }
+
+    fn check_lex(code: &str) {
+        let streamed: Vec<Result<SpannedToken>> = Lexer::new(code).collect();
+        // an error can only be the last item
+        let errors = streamed.iter().filter(|t| t.is_err()).count();
+        assert!(errors <= 1);
+        assert!(errors == 0 || streamed.last().is_some_and(|t| t.is_err()));
+
+        match lex_spanned(code) {
+            Ok(tokens) => {
+                assert_eq!(errors, 0);
+                check_spans(code, &tokens);
+            }
+            Err(crate::error::Error::LexerError(e)) => {
+                assert_eq!(errors, 1);
+                if let Some(span) = e.span() {
+                    assert!(span.start <= span.end && span.end <= code.len());
+                }
+            }
+            Err(e) => panic!("lex returned a non-lexer error: {}", e),
+        }
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn lex_never_panics(code in proptest::prelude::any::<String>()) {
+            check_lex(&code);
+        }
+
+        #[test]
+        fn lex_recovering_covers_input(
+            code in r#"[a-z0-9_'"\\(){}|*+\-.;<>=~#`§ \n]{0,40}"#
+        ) {
+            let (tokens, errors) = lex_recovering(&code);
+            check_spans(&code, &tokens);
+            let error_tokens = tokens.iter().filter(|t| matches!(t.token, ERROR(_))).count();
+            proptest::prop_assert_eq!(error_tokens, errors.len());
+            if let Ok(ok) = lex_spanned(&code) {
+                proptest::prop_assert_eq!(ok, tokens);
+            }
+        }
+
+        #[test]
+        fn lex_never_panics_on_ocaml_like_input(
+            code in r#"[a-zA-Z0-9_'"\\(){}|*+\-.,;:<>=@^!?~$%&#\[\] \n\té]{0,40}"#
+        ) {
+            check_lex(&code);
+        }
+
+        #[test]
+        fn lex_never_panics_on_truncated_programs(
+            cut in 0usize..200,
+            code in proptest::sample::select(vec![
+                "let rec fib (a:int) : int -> int = fun (b:int) -> if a <= 0 then b else fib (a-1) (b*2) in fib 10 1",
+                "(** doc *) let s = \"a\\n\\x41\\u{1F600}\" in {id|raw|id} ^ s (* (* nested *) \"*)\" *)",
+                "let x' = 0x1F + 0b1_0 + 1_000 in 3.14e-2 +. float_of_int x' ; 'c' :: ['\\n'; '\\065']",
+            ])
+        ) {
+            let cut = (0..=cut.min(code.len())).rev().find(|i| code.is_char_boundary(*i)).unwrap_or(0);
+            check_lex(&code[..cut]);
+        }
+    }
+
+    #[test]
+    fn test_no_panic_regressions() {
+        for code in [
+            "x ", " ", "", "1 ", "\u{663}", "x\u{663}", "'", "\"\\", "{", "{a", "(*", "0x", "1e+",
+        ] {
+            check_lex(code);
+        }
+        assert_eq!(lex("x ").unwrap(), vec![VAR("x".to_string())]);
+    }
+
+    #[test]
+    fn test_recovering() {
+        let code = "let x = 1 ` 2 § \"a\\qb\" '\\q' y (* open";
+        let (tokens, errors) = lex_recovering(code);
+        let tokens: Vec<Token> = tokens.into_iter().map(|t| t.token).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                LET,
+                VAR("x".to_string()),
+                EQ,
+                CON(ICON(1)),
+                ERROR("`".to_string()),
+                CON(ICON(2)),
+                ERROR("§".to_string()),
+                ERROR("\"a\\qb\"".to_string()),
+                ERROR("'\\q'".to_string()),
+                VAR("y".to_string()),
+                ERROR("(* open".to_string()),
+            ]
+        );
+        assert!(matches!(
+            errors.as_slice(),
+            [
+                LexerError::ForbiddenChar('`', _),
+                LexerError::ForbiddenChar('§', _),
+                LexerError::EscapeError(_, _),
+                LexerError::EscapeError(_, _),
+                LexerError::CommentError(_),
+            ]
+        ));
+
+        let (tokens, errors) = lex_recovering("1 + 2");
+        assert_eq!(tokens.len(), 3);
+        assert!(errors.is_empty());
     }
 }
@@ -2,65 +2,94 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+#![allow(non_snake_case)]
+
 pub mod error;
 mod evaluator;
 mod lex;
 mod parse;
 mod typechecker;
 mod utils;
-pub use error::Result;
-pub use evaluator::{evaluate, value};
-pub use lex::{lex, Token};
-pub use parse::{parse, ty};
-pub use typechecker::type_check;
+pub use error::{Result, Warning};
+pub use evaluator::{evaluate, evaluate_phrase, value};
+pub use lex::{lex, lex_recovering, lex_spanned, Lexer, Span, SpannedToken, Token};
+pub use parse::{parse, parse_program, parse_spanned, pattern, phrase, ty};
 use std::collections::BTreeMap;
+pub use typechecker::{type_check, type_check_phrase};
+
+/// Types of the variables in scope, by name
+type TypeEnv = BTreeMap<String, ty>;
+/// Values of the variables in scope, by name
+type ValueEnv = BTreeMap<String, Box<value>>;
 
-#[allow(non_snake_case)]
-
-pub fn run_code(
-    code: &str,
-) -> Result<(BTreeMap<String, ty>, BTreeMap<String, Box<value>>, ty, value)> {
-    let tokenlist: Vec<Token> = lex(&code)?;
-    let ast = parse(tokenlist)?.0;
-    let mut type_env: BTreeMap<String, ty> = BTreeMap::new();
-    let typed = type_check(&mut type_env, ast.clone())?;
-    let mut value_env: BTreeMap<String, Box<value>> = BTreeMap::new();
-    let evaluated = evaluate(&mut value_env, ast)?;
+pub fn run_code(code: &str) -> Result<(TypeEnv, ValueEnv, ty, value)> {
+    let mut type_env: TypeEnv = BTreeMap::new();
+    let mut value_env: ValueEnv = BTreeMap::new();
+    let (_, _, typed, evaluated) =
+        run_code_with_persistent_environment(&mut type_env, &mut value_env, code)?;
     Ok((type_env, value_env, typed, evaluated))
 }
 
+/// Runs the phrases of a program one after another. Declared variables are
+/// added to the environments; the type and value of the last phrase are
+/// returned. A phrase that fails leaves both environments as they were.
 pub fn run_code_with_persistent_environment<'a>(
-    type_env: &'a mut BTreeMap<String, ty>,
-    value_env: &'a mut BTreeMap<String, Box<value>>,
+    type_env: &'a mut TypeEnv,
+    value_env: &'a mut ValueEnv,
     code: &'a str,
-) -> Result<(
-    &'a mut BTreeMap<String, ty>,
-    &'a mut BTreeMap<String, Box<value>>,
-    ty,
-    value,
-)> {
-    let tokenlist: Vec<Token> = lex(&code)?;
-    let ast = parse(tokenlist)?.0;
-    let typed = type_check(type_env, ast.clone())?;
-    let evaluated = evaluate(value_env, ast)?;
+) -> Result<(&'a mut TypeEnv, &'a mut ValueEnv, ty, value)> {
+    let (type_env, value_env, typed, evaluated, _) =
+        run_code_with_warnings(type_env, value_env, code)?;
     Ok((type_env, value_env, typed, evaluated))
 }
 
+/// Like `run_code_with_persistent_environment`, but also returns the warnings
+/// of the typechecker, e.g. for `e1; e2` where `e1` is not of type unit.
+pub fn run_code_with_warnings<'a>(
+    type_env: &'a mut TypeEnv,
+    value_env: &'a mut ValueEnv,
+    code: &'a str,
+) -> Result<(&'a mut TypeEnv, &'a mut ValueEnv, ty, value, Vec<Warning>)> {
+    let tokenlist: Vec<SpannedToken> = lex_spanned(code)?;
+    let mut last = None;
+    let mut warnings = vec![];
+    for phrase in parse_program(tokenlist)? {
+        let mut phrase_warnings = vec![];
+        let mut new_type_env = type_env.clone();
+        let typed = type_check_phrase(&mut new_type_env, phrase.clone(), &mut phrase_warnings)?;
+        let mut new_value_env = value_env.clone();
+        let evaluated = evaluate_phrase(&mut new_value_env, phrase)?;
+        *type_env = new_type_env;
+        *value_env = new_value_env;
+        warnings.append(&mut phrase_warnings);
+        last = Some((typed, evaluated));
+    }
+    // `parse_program` does not return empty programs
+    let (typed, evaluated) = last.expect("empty program");
+    Ok((type_env, value_env, typed, evaluated, warnings))
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::evaluator::value;
     use super::run_code;
+    use crate::evaluator::value;
     use crate::lex::Token;
     use crate::parse::ty;
     use std::collections::BTreeMap;
 
+    /// Type and value of the program `src`, e.g. `int list = [1; 2]`
+    fn eval(src: &str) -> String {
+        let (_, _, typed, evaluated) = run_code(src).unwrap();
+        format!("{} = {}", typed, evaluated)
+    }
+
     #[test]
     fn test_all_int_1() {
         let src = "let x = 5 in x";
         println!("Code: {} \n", src);
-        let tokenlist: Vec<Token> = super::lex(&src).unwrap();
+        let tokenlist: Vec<Token> = super::lex(src).unwrap();
         println!("After Lex: {:?} \n", tokenlist);
-        let ast = super::parse(tokenlist).unwrap().0;
+        let ast = super::parse(tokenlist).unwrap();
         println!("After Parse: {:?} \n", ast);
         let mut map: BTreeMap<String, ty> = BTreeMap::new();
         let typed = super::type_check(&mut map, ast.clone()).unwrap();
@@ -75,9 +104,9 @@ mod tests {
     fn test_all_int_2() {
         let src = "let rec fib (a:int) : int -> int -> int = fun (b:int) -> fun (n:int) -> if n <= 0 then a else fib (b) (a+b) (n-1) in fib 0 1 8";
         println!("Code: {} \n", src);
-        let tokenlist: Vec<Token> = super::lex(&src).unwrap();
+        let tokenlist: Vec<Token> = super::lex(src).unwrap();
         println!("After Lex: {:?} \n", tokenlist);
-        let ast = super::parse(tokenlist).unwrap().0;
+        let ast = super::parse(tokenlist).unwrap();
         println!("After Parse: {:?} \n", ast);
         let mut map: BTreeMap<String, ty> = BTreeMap::new();
         let typed = super::type_check(&mut map, ast.clone()).unwrap();
@@ -93,9 +122,9 @@ mod tests {
     fn test_all_bool_1() {
         let src = "let x = false in if x then true else false";
         println!("Code: {} \n", src);
-        let tokenlist: Vec<Token> = super::lex(&src).unwrap();
+        let tokenlist: Vec<Token> = super::lex(src).unwrap();
         println!("After Lex: {:?} \n", tokenlist);
-        let ast = super::parse(tokenlist).unwrap().0;
+        let ast = super::parse(tokenlist).unwrap();
         println!("After Parse: {:?} \n", ast);
         let mut map: BTreeMap<String, ty> = BTreeMap::new();
         let typed = super::type_check(&mut map, ast.clone()).unwrap();
@@ -112,4 +141,901 @@ mod tests {
         let src = "let rec loop (x:int) : bool = if x<=1 then true else loop (x-1) in loop 9999999";
         let _ = run_code(src).unwrap();
     }
+
+    #[test]
+    fn test_all_string_char() {
+        let src = "let f = fun (s:string) -> 'c' in f \"hello\\n\"";
+        let (_, _, typed, evaluated) = run_code(src).unwrap();
+        assert_eq!(typed, ty::Char);
+        assert!(matches!(evaluated, value::CharValue('c')));
+    }
+
+    #[test]
+    fn test_all_float() {
+        let src =
+            "let x = float_of_int 3 in let y = x *. 2.5 -. 1e-1 /. 0.5 in int_of_float (y +. 0.3)";
+        let (_, _, typed, evaluated) = run_code(src).unwrap();
+        assert_eq!(typed, ty::Int);
+        assert!(matches!(evaluated, value::IntValue(7)));
+
+        let src = "fun (x:float) -> x +. 1";
+        assert!(run_code(src).is_err());
+    }
+
+    #[test]
+    fn test_recovering() {
+        // the parser skips error tokens, so the later phases still run
+        let (tokens, errors) = super::lex_recovering("let x = 1 § in x + `1");
+        assert_eq!(errors.len(), 2);
+        assert!(super::parse_spanned(tokens.clone()).is_ok());
+        let tokens: Vec<Token> = tokens.into_iter().map(|t| t.token).collect();
+        let ast = super::parse(tokens).unwrap();
+        let typed = super::type_check(&mut BTreeMap::new(), ast.clone()).unwrap();
+        assert_eq!(typed, ty::Int);
+        let evaluated = super::evaluate(&mut BTreeMap::new(), ast).unwrap();
+        assert!(matches!(evaluated, value::IntValue(2)));
+    }
+
+    #[test]
+    fn test_error_locations() {
+        let src = "let x = 1 in\nx + (2 * true)";
+        let err = run_code(src).unwrap_err();
+        let span = err.span().unwrap();
+        assert_eq!(&src[span.start..span.end], "2 * true");
+        assert_eq!((span.line, span.column), (2, 6));
+        assert!(matches!(
+            err.without_location(),
+            crate::error::Error::TypeCheckError(_)
+        ));
+
+        let src = "let f = fun (x:int) -> x in f 1 + undefined";
+        let span = run_code(src).unwrap_err().span().unwrap();
+        assert_eq!(&src[span.start..span.end], "undefined");
+
+        // evaluation errors point into the body of the applied function
+        let src = "let rec f (x:int) : int = if x then 1 else 2 in f 0";
+        let ast = super::parse_spanned(super::lex_spanned(src).unwrap()).unwrap();
+        let err = super::evaluate(&mut BTreeMap::new(), ast).unwrap_err();
+        let span = err.span().unwrap();
+        assert_eq!(&src[span.start..span.end], "if x then 1 else 2");
+    }
+
+    #[test]
+    fn test_parser_errors() {
+        let message = |src| run_code(src).unwrap_err().to_string();
+        // at the top level, `let` without `in` is a declaration
+        let expression_message = |src| {
+            let tokens = super::lex_spanned(src).unwrap();
+            super::parse_spanned(tokens).unwrap_err().to_string()
+        };
+
+        let msg = expression_message("let x = 1 x");
+        assert!(msg.contains("expected `in` after the let-binding but reached the end of input"));
+        assert!(msg.contains("hint"));
+
+        let msg = expression_message("let x = 1 then 2");
+        assert!(msg
+            .contains("expected `in` after the let-binding but found `then` at line 1, column 11"));
+
+        let msg = message("if true then 1");
+        assert!(msg.contains("expected `else` after the `then` branch"));
+        assert!(msg.contains("must have an `else` branch"));
+
+        let msg = message("1 + (2 * \n 3");
+        assert!(msg.contains("expected `)`"));
+        assert!(msg.contains("the `(` at line 1, column 5 is never closed"));
+
+        let msg = message("fun x = x");
+        assert!(
+            msg.contains("expected `->` after the parameters but found `=` at line 1, column 7")
+        );
+        assert!(msg.contains("`fun` uses `->`"));
+
+        let msg = message("1 + )");
+        assert!(msg.contains("expected an expression but found `)` at line 1, column 5"));
+
+        let msg = message("fun (x:list) -> x");
+        assert!(msg.contains("expected a type but found `list`"));
+    }
+
+    #[test]
+    fn test_parse_long_program() {
+        // parsing is linear in the number of tokens: the parser looks at the
+        // remaining tokens a bounded number of times per token
+        let program = |lines: usize| {
+            let mut src = String::new();
+            for i in 0..100 {
+                src.push_str(&format!(
+                    "let x{} = {} * 2 + (fun (y:int) -> y) 1 in\n",
+                    i, i
+                ));
+            }
+            for i in 0..lines {
+                src.push_str(&format!("x{} + (x{} * 2 + 1) +\n", i % 100, (i + 1) % 100));
+            }
+            src.push('0');
+            super::lex(&src).unwrap()
+        };
+        let (short, long) = (program(500), program(5000));
+        super::parse(long.clone()).unwrap();
+
+        let steps_short = crate::parse::parse_steps(&short).unwrap();
+        let steps_long = crate::parse::parse_steps(&long).unwrap();
+        assert!(steps_long <= 10 * long.len());
+        // ten times the lines take at most ten times the steps
+        assert!(steps_long * short.len() <= steps_short * long.len() * 11 / 10);
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let eval = |src| format!("{:?}", run_code(src).unwrap().3);
+        assert_eq!(eval("1 < 2"), "BoolValue(true)");
+        assert_eq!(eval("2 > 3"), "BoolValue(false)");
+        assert_eq!(eval("3 >= 3"), "BoolValue(true)");
+        assert_eq!(eval("3 <= 2"), "BoolValue(false)");
+        assert_eq!(eval("1 <> 2"), "BoolValue(true)");
+        // binds looser than arithmetic
+        assert_eq!(eval("1 + 2 * 3 = 7"), "BoolValue(true)");
+        assert_eq!(eval("2 * 3 > 1 + 4"), "BoolValue(true)");
+        // polymorphic, and left associative like in OCaml
+        assert_eq!(eval("true = false"), "BoolValue(false)");
+        assert_eq!(eval("1 < 2 = true"), "BoolValue(true)");
+        assert_eq!(eval("\"abc\" < \"abd\""), "BoolValue(true)");
+        assert_eq!(eval("'a' <> 'a'"), "BoolValue(false)");
+        assert_eq!(eval("1.5 >= 1.25"), "BoolValue(true)");
+        assert_eq!(
+            eval("let nan = 0.0 /. 0.0 in nan = nan"),
+            "BoolValue(false)"
+        );
+        assert_eq!(eval("let x = 1 = 1 in if x then 1 else 2"), "IntValue(1)");
+
+        assert!(run_code("1 = true").is_err());
+        assert!(run_code("(fun (x:int) -> x) = (fun (x:int) -> x)").is_err());
+    }
+
+    #[test]
+    fn test_function_sugar() {
+        let eval = |src| format!("{:?}", run_code(src).unwrap().3);
+        assert_eq!(eval("(fun x y z -> x + y * z) 1 2 3"), "IntValue(7)");
+        assert_eq!(eval("let f x y = x - y in f 5 3"), "IntValue(2)");
+        assert_eq!(eval("let f (x:int) y = x * y in f 4 5"), "IntValue(20)");
+        assert_eq!(
+            eval("let rec pow (x:int) (n:int) : int = if n <= 0 then 1 else x * pow x (n - 1) in pow 2 10"),
+            "IntValue(1024)"
+        );
+        assert_eq!(
+            eval("let rec fact n = if n = 0 then 1 else n * fact (n - 1) in fact 5"),
+            "IntValue(120)"
+        );
+        assert_eq!(
+            eval("let rec sum = fun acc n -> if n = 0 then acc else sum (acc + n) (n - 1) in sum 0 100"),
+            "IntValue(5050)"
+        );
+
+        // the sugar is the same as the curried form
+        let desugared = |src| format!("{:?}", super::parse(super::lex(src).unwrap()).unwrap());
+        assert_eq!(
+            desugared("let f x (y:int) = x in f"),
+            desugared("let f = fun x -> fun (y:int) -> x in f")
+        );
+        assert_eq!(
+            desugared("let rec f (x:int) (y:bool) : int = x in f"),
+            desugared("let rec f (x:int) : bool -> int = fun (y:bool) -> x in f")
+        );
+
+        // the annotation of `let rec f : t = fun ...` is the type of the function
+        assert_eq!(
+            eval("let rec f : int -> int = fun x -> x in f 1"),
+            "IntValue(1)"
+        );
+        assert_eq!(
+            eval("let rec f : int -> int = fun x -> if x = 0 then 0 else f (x - 1) in f 3"),
+            "IntValue(0)"
+        );
+        assert_eq!(
+            eval("let rec f : int -> bool -> int = fun (x:int) y -> if y then x else f x true in f 2 false"),
+            "IntValue(2)"
+        );
+        assert_eq!(
+            desugared("let rec f : int -> int = fun x -> x in f"),
+            desugared("let rec f (x:int) : int = x in f")
+        );
+        assert!(run_code("let rec f : int -> int = fun (x:bool) -> 1 in f").is_err());
+        assert!(run_code("let rec f : int = fun x -> x in f").is_err());
+        assert!(run_code("let rec f : int -> bool = fun x -> x in f 1").is_err());
+
+        let msg = run_code("let rec x = 1 in x").unwrap_err().to_string();
+        assert!(msg.contains("expected a function after `let rec` but found `1`"));
+        assert!(run_code("fun -> 1").is_err());
+    }
+
+    #[test]
+    fn test_type_inference() {
+        let infer = |src| {
+            let mut env = BTreeMap::new();
+            let ast = super::parse(super::lex(src).unwrap()).unwrap();
+            super::type_check(&mut env, ast)
+        };
+        let arrow = |t1, t2| ty::Arrow(Box::new(t1), Box::new(t2));
+        assert_eq!(infer("fun x -> x + 1").unwrap(), arrow(ty::Int, ty::Int));
+        assert_eq!(
+            infer("fun f -> fun x -> f (f x)").unwrap(),
+            arrow(arrow(ty::Var(2), ty::Var(2)), arrow(ty::Var(2), ty::Var(2)))
+        );
+        assert_eq!(
+            infer("let rec f x = if x <= 0 then 0 else f (x - 1) in f").unwrap(),
+            arrow(ty::Int, ty::Int)
+        );
+        // parameters are not visible outside of their function
+        assert!(infer("let f = fun x -> x in x").is_err());
+    }
+
+    #[test]
+    fn test_unification_failures() {
+        let error = |src| {
+            let ast = super::parse(super::lex(src).unwrap()).unwrap();
+            let err = super::type_check(&mut BTreeMap::new(), ast).unwrap_err();
+            format!("{:?}", err)
+        };
+        assert!(error("fun x -> x x").contains("InfiniteType"));
+        assert!(error("fun x -> if x then x + 1 else 0").contains("WrongIfType"));
+        assert!(error("fun x -> x + 1 +. 2.0").contains("ArgumentError"));
+        assert!(error("let f = fun x -> x + 1 in f true").contains("WrongArgument"));
+        assert!(error("fun f -> f 1 + f true").contains("WrongArgument"));
+        assert!(error("if true then 1 else false").contains("UnequalIfTypes"));
+        assert!(error("1 2").contains("MissingFunction"));
+    }
+
+    #[test]
+    fn test_let_polymorphism() {
+        let infer = |src| {
+            let ast = super::parse(super::lex(src).unwrap()).unwrap();
+            super::type_check(&mut BTreeMap::new(), ast)
+        };
+        // let-bound functions are polymorphic, lambda-bound ones are not
+        assert_eq!(
+            infer("let id = fun x -> x in if id true then id 1 else 2").unwrap(),
+            ty::Int
+        );
+        assert!(infer("(fun id -> if id true then id 1 else 2) (fun x -> x)").is_err());
+        assert_eq!(
+            infer("let k = fun x -> fun y -> x in k 'c' (k 1 true)").unwrap(),
+            ty::Char
+        );
+        // a variable bound by an enclosing lambda is not generalized
+        assert!(infer("fun y -> let f = fun x -> y in if f 1 then f true else 0").is_err());
+        // recursive functions are monomorphic inside their own body
+        assert!(infer("let rec f x = if true then x else f true in f 1").is_err());
+    }
+
+    #[test]
+    fn test_programs() {
+        let src = "let x = 20
+let rec fact n = if n = 0 then 1 else n * fact (n - 1)
+let id x = x;;
+fact 5;;
+id true;;
+let y = id x + fact 3";
+        let (type_env, value_env, typed, evaluated) = run_code(src).unwrap();
+        assert_eq!(typed, ty::Int);
+        assert_eq!(format!("{:?}", evaluated), "IntValue(26)");
+        assert_eq!(type_env["x"], ty::Int);
+        assert_eq!(
+            type_env["id"],
+            ty::Forall(
+                vec![0],
+                Box::new(ty::Arrow(Box::new(ty::Var(0)), Box::new(ty::Var(0))))
+            )
+        );
+        assert_eq!(format!("{:?}", value_env["y"]), "IntValue(26)");
+        assert_eq!(
+            format!("{:?}", run_code("1;; 2;;").unwrap().3),
+            "IntValue(2)"
+        );
+        assert_eq!(
+            format!(
+                "{:?}",
+                run_code("let x = 1 in x + 1;; let y = 3").unwrap().3
+            ),
+            "IntValue(3)"
+        );
+
+        // declarations stay visible to later runs
+        let mut type_env = BTreeMap::new();
+        let mut value_env = BTreeMap::new();
+        super::run_code_with_persistent_environment(&mut type_env, &mut value_env, "let a = 4")
+            .unwrap();
+        let result = super::run_code_with_persistent_environment(
+            &mut type_env,
+            &mut value_env,
+            "let b = a * a;; b + 1",
+        )
+        .unwrap();
+        assert_eq!(format!("{:?}", result.3), "IntValue(17)");
+        // a failing phrase declares nothing
+        let mut run = |src| {
+            super::run_code_with_persistent_environment(&mut type_env, &mut value_env, src)
+                .map(|result| format!("{:?}", result.3))
+        };
+        assert!(run("let c = (let rec f x = x in f 1) + true").is_err());
+        assert!(run("f").is_err());
+        assert!(run("c").is_err());
+        assert_eq!(run("let c = 5;; c + a").unwrap(), "IntValue(9)");
+
+        // trailing garbage and missing separators are errors
+        let message = |src| run_code(src).unwrap_err().to_string();
+        assert!(message("1 + 2 )").contains("expected `;;` or the end of the input but found `)`"));
+        assert!(message("1 let x = 2").contains("found `let`"));
+        // an expression after a declaration needs `;;`
+        let msg = message("let x = 1 if true then x else 2");
+        assert!(msg.contains("expected `;;` or a declaration after the declaration but found `if`"));
+        assert!(message("let x = 1 let y = 2 in y").contains("found `let`"));
+        assert!(run_code("let x = 1 let y = 2;; x + y").is_ok());
+        assert!(run_code("let x = 1;; if true then x else 2").is_ok());
+        assert!(message(";;").contains("expected an expression or a `let` declaration"));
+        let tokens = super::lex("1 2 )").unwrap();
+        assert!(super::parse(tokens).is_err());
+    }
+
+    #[test]
+    fn test_let_scope() {
+        // the variable of `let ... in` is only visible in its body
+        let programs = [
+            "if false then (let x = 1 in x) else x",
+            "(let x = 5 in x) + x",
+            "let x = 1 in x;; x",
+            "(let rec f x = x in f 1) + f 2",
+        ];
+        for src in programs {
+            let msg = run_code(src).unwrap_err().to_string();
+            assert!(msg.contains("is unbound"), "{}: {}", src, msg);
+
+            // the evaluator scopes it the same way
+            let mut env = BTreeMap::new();
+            let mut last = Ok(value::IntValue(0));
+            for phrase in super::parse_program(super::lex_spanned(src).unwrap()).unwrap() {
+                let ast = match phrase {
+                    super::phrase::Let(_, ast) | super::phrase::Exp(ast) => ast,
+                    super::phrase::Type(..) => continue,
+                };
+                last = super::evaluate(&mut env, ast);
+            }
+            let msg = last.unwrap_err().to_string();
+            assert!(msg.contains("is unbound"), "{}: {}", src, msg);
+        }
+    }
+
+    #[test]
+    fn test_tuples() {
+        assert_eq!(eval("(1, true)"), "int * bool = (1, true)");
+        assert_eq!(eval("fst (1, 'a') + snd (true, 2)"), "int = 3");
+        assert_eq!(eval("let (a, b) = (1, 2) in a - b"), "int = -1");
+        assert_eq!(eval("let a, (b, _) = 1, (2, 3) in a + b"), "int = 3");
+        assert_eq!(
+            eval("let swap (a, b) = (b, a) in swap (1, 'c')"),
+            "char * int = ('c', 1)"
+        );
+        assert_eq!(eval("(1, 2) < (1, 3)"), "bool = true");
+        assert_eq!(
+            eval("let sum_diff a b = (a + b, a - b) in snd (sum_diff 7 2)"),
+            "int = 5"
+        );
+        // the variables of the pattern are local to the body
+        assert_eq!(
+            eval("let x = 1 in (let (x, y) = (2, 3) in x + y) + x"),
+            "int = 6"
+        );
+        assert!(run_code("(let (a, b) = (true, 2) in b) + a").is_err());
+        let typed = run_code("let swap (a, b) = (b, a)").unwrap().2;
+        assert_eq!(typed.to_string(), "'a * 'b -> 'b * 'a");
+
+        // top-level declarations can destructure as well
+        let (type_env, _, _, evaluated) = run_code("let (x, y) = (1, \"s\");; y").unwrap();
+        assert_eq!(type_env["x"], ty::Int);
+        assert_eq!(type_env["y"], ty::String);
+        assert_eq!(format!("{:?}", evaluated), "StringValue(\"s\")");
+
+        assert!(run_code("let (a, b) = (1, 2, 3) in a").is_err());
+        assert!(run_code("let (a, a) = (1, 2) in a").is_err());
+        assert!(run_code("fst (1, 2, 3)").is_err());
+    }
+
+    #[test]
+    fn test_type_display() {
+        let show = |src| {
+            let ast = super::parse(super::lex(src).unwrap()).unwrap();
+            super::type_check(&mut BTreeMap::new(), ast)
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(
+            show("fun (f : int -> int) x -> f x"),
+            "(int -> int) -> int -> int"
+        );
+        assert_eq!(
+            show("fun (p : int * (bool * char)) -> p"),
+            "int * (bool * char) -> int * (bool * char)"
+        );
+        assert_eq!(
+            show("fun f g x -> g (f x)"),
+            "('a -> 'b) -> ('b -> 'c) -> 'a -> 'c"
+        );
+        assert_eq!(show("fun x -> (x, fun y -> y)"), "'a -> 'a * ('b -> 'b)");
+        assert_eq!(show("fun (x : (float)) -> x"), "float -> float");
+    }
+
+    #[test]
+    fn test_lists() {
+        assert_eq!(eval("[]"), "'a list = []");
+        assert_eq!(eval("[1; 2; 3]"), "int list = [1; 2; 3]");
+        assert_eq!(eval("[1; 2;]"), "int list = [1; 2]");
+        assert_eq!(eval("1 :: 2 :: [3]"), "int list = [1; 2; 3]");
+        assert_eq!(eval("[1] @ [2] @ []"), "int list = [1; 2]");
+        assert_eq!(eval("0 :: [1] @ [2]"), "int list = [0; 1; 2]");
+        assert_eq!(eval("1 + 1 :: []"), "int list = [2]");
+        assert_eq!(
+            eval("[1, true; 2, false]"),
+            "(int * bool) list = [(1, true); (2, false)]"
+        );
+        assert_eq!(eval("[[1.0]; []]"), "float list list = [[1.]; []]");
+        assert_eq!(eval("[\"a\"] = [\"a\"]"), "bool = true");
+        assert_eq!(eval("[1; 2] < [1; 2; 0]"), "bool = true");
+        assert_eq!(eval("let id l = l in id [id]"), "('a -> 'a) list = [<fun>]");
+        assert_eq!(
+            eval("let rec range n = if n = 0 then [] else range (n - 1) @ [n] in range 4"),
+            "int list = [1; 2; 3; 4]"
+        );
+        assert_eq!(
+            eval("fun (l : int list) -> l"),
+            "int list -> int list = <fun>"
+        );
+        assert_eq!(
+            eval("let xs = [] in (1 :: xs, true :: xs)"),
+            "int list * bool list = ([1], [true])"
+        );
+
+        assert!(run_code("[1; true]").is_err());
+        assert!(run_code("1 :: 2").is_err());
+        assert!(run_code("[1] @ [true]").is_err());
+        let msg = run_code("[1; 2").unwrap_err().to_string();
+        assert!(msg.contains("reached the end of input"));
+        let msg = run_code("[1 2)").unwrap_err().to_string();
+        assert!(msg.contains("the `[` at line 1, column 1 is never closed"));
+    }
+
+    #[test]
+    fn test_match() {
+        let src = "let rec length l = match l with [] -> 0 | _ :: xs -> 1 + length xs
+let rec map f l = match l with
+  | [] -> []
+  | x :: xs -> f x :: map f xs
+let rec sum = fun l -> match l with [] -> 0 | x :: xs -> x + sum xs;;
+";
+        assert_eq!(eval(&format!("{} length [1; 2; 3]", src)), "int = 3");
+        assert_eq!(
+            eval(&format!("{} map (fun x -> x * x) [1; 2; 3]", src)),
+            "int list = [1; 4; 9]"
+        );
+        assert_eq!(
+            eval(&format!("{} sum (map fst [(1, 'a'); (2, 'b')])", src)),
+            "int = 3"
+        );
+        assert_eq!(
+            eval(&format!("{} map", src)),
+            "('a -> 'b) -> 'a list -> 'b list = <fun>"
+        );
+
+        // constants, tuples and nested patterns
+        assert_eq!(
+            eval("match 3 with 0 -> \"zero\" | 1 -> \"one\" | _ -> \"many\""),
+            "string = \"many\""
+        );
+        assert_eq!(
+            eval("match (true, 'c') with (false, _) -> 0 | (true, 'c') -> 1 | _ -> 2"),
+            "int = 1"
+        );
+        assert_eq!(
+            eval("match [(1, [2]); (3, [])] with [(a, [b]); (c, d)] -> a + b + c | _ -> 0"),
+            "int = 6"
+        );
+        assert_eq!(
+            eval("match [1; 2; 3] with a :: b :: rest -> (a + b, rest) | _ -> (0, [])"),
+            "int * int list = (3, [3])"
+        );
+        assert_eq!(
+            eval("match \"s\" with \"t\" -> false | x -> x = \"s\""),
+            "bool = true"
+        );
+        // the last case takes the `match` in its body with it
+        assert_eq!(
+            eval("match 1 with 0 -> 0 | n -> match n with 1 -> 10 | _ -> 20"),
+            "int = 10"
+        );
+        // variables of a case are only visible in its body
+        assert!(run_code("(match 1 with x -> x) + x").is_err());
+        assert_eq!(eval("let x = 1 in (match 2 with x -> x) + x"), "int = 3");
+        assert_eq!(
+            eval("let y = true in let z = (match 3 with y -> y) in if y then 1 else 0"),
+            "int = 1"
+        );
+
+        let err = run_code("match [1] with [] -> 0").unwrap_err();
+        assert!(err.to_string().contains("Match_failure"));
+        assert!(err.to_string().contains("[1]"));
+        assert!(run_code("match 1 with true -> 0 | _ -> 1").is_err());
+        assert!(run_code("match 1 with 1 -> 0 | _ -> false").is_err());
+        assert!(run_code("match (1, 2) with (x, x) -> x").is_err());
+        assert!(run_code("match [1] with x :: 2 -> x").is_err());
+        assert!(run_code("match 1 with").is_err());
+    }
+
+    #[test]
+    fn test_annotations() {
+        assert_eq!(eval("(1 + 2 : int)"), "int = 3");
+        assert_eq!(eval("([] : int list)"), "int list = []");
+        assert_eq!(eval("let x : float = 1.5 in x"), "float = 1.5");
+        assert_eq!(eval("let f (x:int) : bool = x > 0 in f 2"), "bool = true");
+        assert_eq!(
+            eval("let f x : int list = [x] in f"),
+            "int -> int list = <fun>"
+        );
+        assert_eq!(
+            eval("let id x = x;; (id : int -> int)"),
+            "int -> int = <fun>"
+        );
+        assert_eq!(
+            eval("let pair : int * char = (1, 'a');; pair"),
+            "int * char = (1, 'a')"
+        );
+        // partial annotations of `let rec` are checked too
+        assert_eq!(
+            eval("let rec f (n:int) acc = if n = 0 then acc else f (n - 1) (acc + 1) in f"),
+            "int -> int -> int = <fun>"
+        );
+        assert_eq!(
+            eval("let rec f n : float = if n = 0 then 0. else f (n - 1) in f 3"),
+            "float = 0."
+        );
+
+        let err = run_code("(1 : bool)").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("has type int but is annotated with type bool"));
+        assert!(run_code("let x : int = 'c' in x").is_err());
+        assert!(run_code("let f (x:int) : bool = x in f").is_err());
+        assert!(run_code("let rec f (x:bool) = if x then 0 else f 1 in f").is_err());
+        assert!(run_code("let rec f x : int = if x then 1.5 else f x in f").is_err());
+        assert!(run_code("(1 : )").is_err());
+    }
+
+    #[test]
+    fn test_negation() {
+        assert_eq!(eval("1 - -2"), "int = 3");
+        assert_eq!(eval("let f x = x * 2 in f (-3)"), "int = -6");
+        assert_eq!(eval("let x = 4 in ~- x"), "int = -4");
+        assert_eq!(eval("let f x = x + 1 in f ~-2"), "int = -1");
+        assert_eq!(eval("-1.5 +. 1."), "float = -0.5");
+        assert_eq!(eval("let x = 2. in -. x *. 3."), "float = -6.");
+        assert_eq!(eval("~-. 0.25"), "float = -0.25");
+        assert_eq!(eval("- - 5"), "int = 5");
+        assert_eq!(eval("[-1; 2 - 3]"), "int list = [-1; -1]");
+        // negation binds tighter than `*` and looser than application
+        assert_eq!(eval("-2 * 3 + 1"), "int = -5");
+        assert_eq!(eval("let f x = x + 1 in - f 2"), "int = -3");
+        assert_eq!(eval("let x = 3 in x - 1"), "int = 2");
+        assert_eq!(eval("match -1 with -1 -> true | _ -> false"), "bool = true");
+        // integer arithmetic wraps around instead of overflowing
+        assert_eq!(
+            eval("9223372036854775807 + 1"),
+            "int = -9223372036854775808"
+        );
+        assert_eq!(
+            eval("-9223372036854775807 - 2"),
+            "int = 9223372036854775807"
+        );
+        assert_eq!(
+            eval("4611686018427387904 * 2"),
+            "int = -9223372036854775808"
+        );
+        // min_int is written as a negated literal
+        assert_eq!(eval("-9223372036854775808"), "int = -9223372036854775808");
+        assert_eq!(
+            eval("-9223372036854775808 = -9223372036854775807 - 1"),
+            "bool = true"
+        );
+        assert_eq!(
+            eval("match -9223372036854775808 with -9223372036854775808 -> 1 | _ -> 0"),
+            "int = 1"
+        );
+        assert!(run_code("9223372036854775808").is_err());
+
+        let desugared = |src| format!("{:?}", super::parse(super::lex(src).unwrap()).unwrap());
+        assert_eq!(desugared("-7"), "Con(Icon(-7))");
+        assert_eq!(desugared("-x"), "Uapp(Neg, Var(\"x\"))");
+        assert_eq!(desugared("f -1"), "Oapp(Sub, Var(\"f\"), Con(Icon(1)))");
+
+        assert!(run_code("-true").is_err());
+        assert!(run_code("-. 1").is_err());
+        assert!(run_code("let x = 1. in - x").is_err());
+        assert!(run_code("match 1 with -x -> x").is_err());
+    }
+
+    #[test]
+    fn test_unit_and_sequences() {
+        assert_eq!(eval("()"), "unit = ()");
+        assert_eq!(eval("ignore 1; 2"), "int = 2");
+        assert_eq!(eval("ignore"), "'a -> unit = <fun>");
+        assert_eq!(eval("let f () = 3 in f ()"), "int = 3");
+        assert_eq!(eval("let () = ignore 1;; ()"), "unit = ()");
+        assert_eq!(eval("fun (x : unit) -> x"), "unit -> unit = <fun>");
+        assert_eq!(eval("begin 1 + 2 end * 3"), "int = 9");
+        assert_eq!(eval("begin end"), "unit = ()");
+        assert_eq!(eval("(ignore 1; ignore 2; )"), "unit = ()");
+        assert_eq!(eval("match () with () -> ()"), "unit = ()");
+        assert_eq!(eval("[(); ()] = [(); ()]"), "bool = true");
+        // `;` is looser than `if` and `,`, the bodies of `let` and `fun`
+        // take it with them
+        assert_eq!(eval("if true then ignore 1 else (); 5"), "int = 5");
+        assert_eq!(eval("let x = 1 in ignore x; x + 1"), "int = 2");
+        assert_eq!(eval("(fun x -> ignore x; x) 4"), "int = 4");
+        assert_eq!(eval("ignore 1; 2, 3"), "int * int = (2, 3)");
+        assert_eq!(eval("[1; 2]"), "int list = [1; 2]");
+
+        let phrase = super::parse_program(super::lex_spanned("1; ()").unwrap())
+            .unwrap()
+            .remove(0);
+        let mut warnings = vec![];
+        let t = super::type_check_phrase(&mut BTreeMap::new(), phrase, &mut warnings).unwrap();
+        assert_eq!(t, ty::Unit);
+        assert_eq!(
+            warnings.iter().map(|w| w.to_string()).collect::<Vec<_>>(),
+            vec!["Warning: this expression should have type unit but has type int at line 1, column 1"]
+        );
+
+        // warnings of the phrases of a program
+        let (mut type_env, mut value_env) = (BTreeMap::new(), BTreeMap::new());
+        let result =
+            super::run_code_with_warnings(&mut type_env, &mut value_env, "let x = 1; 2;; x; ()")
+                .unwrap();
+        assert_eq!(result.3.to_string(), "()");
+        let messages: Vec<_> = result.4.iter().map(|w| w.to_string()).collect();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].ends_with("has type int at line 1, column 9"));
+        assert!(messages[1].ends_with("has type int at line 1, column 16"));
+        // the old entry point runs the same program, without the warnings
+        let result =
+            super::run_code_with_persistent_environment(&mut type_env, &mut value_env, "x; ()")
+                .unwrap();
+        assert_eq!(result.3.to_string(), "()");
+
+        assert!(run_code("begin 1").is_err());
+        assert!(run_code("1 end").is_err());
+        assert!(run_code("() + 1").is_err());
+        assert!(run_code("let f () = 1 in f 2").is_err());
+    }
+
+    #[test]
+    fn test_mutual_recursion() {
+        let parity = "let rec even n = if n = 0 then true else odd (n - 1)
+and odd n = if n = 0 then false else even (n - 1)";
+        assert_eq!(eval(&format!("{} in even 10", parity)), "bool = true");
+        assert_eq!(eval(&format!("{} in odd 7", parity)), "bool = true");
+        assert_eq!(eval(&format!("{};; odd", parity)), "int -> bool = <fun>");
+        assert_eq!(
+            eval(&format!("{};; (even 3, odd 3)", parity)),
+            "bool * bool = (false, true)"
+        );
+        assert_eq!(
+            eval(
+                "let rec even (n:int) : bool = if n = 0 then true else odd (n - 1)
+                 and odd (n:int) : bool = if n = 0 then false else even (n - 1)
+                 in even 4"
+            ),
+            "bool = true"
+        );
+        // partially annotated and polymorphic groups
+        assert_eq!(
+            eval(
+                "let rec evens l = match l with [] -> [] | x :: xs -> x :: odds xs
+                 and odds (l : int list) = match l with [] -> [] | _ :: xs -> evens xs
+                 in (evens [1; 2; 3; 4; 5], odds [1; 2; 3])"
+            ),
+            "int list * int list = ([1; 3; 5], [2])"
+        );
+        assert_eq!(
+            eval("let rec f x = g x and g = fun y -> y;; (f 1, f true)"),
+            "int * bool = (1, true)"
+        );
+        // more than two functions, the later ones see the earlier ones
+        assert_eq!(
+            eval(
+                "let rec a n = if n = 0 then \"a\" else b (n - 1)
+                 and b n = if n = 0 then \"b\" else c (n - 1)
+                 and c n = if n = 0 then \"c\" else a (n - 1)
+                 in (a 4, c 0)"
+            ),
+            "string * string = (\"b\", \"c\")"
+        );
+
+        assert_eq!(
+            eval("let rec f : int -> int = fun x -> g x and g y = y + 1 in f 1"),
+            "int = 2"
+        );
+        // the functions are only visible in the body of `in`
+        assert!(run_code("let y = let rec f x = g x and g y = y in f 1;; f").is_err());
+        assert_eq!(
+            eval("let f = 1;; let y = let rec f x = g x and g y = y in f 2;; (f, y)"),
+            "int * int = (1, 2)"
+        );
+        assert!(run_code("let rec f x = g x and g y = y + 1 in f true").is_err());
+        assert!(run_code("let rec f (x:int) : bool = g x and g y = y in f").is_err());
+        assert!(run_code("let rec f x = x and 1 in f").is_err());
+        assert!(run_code("let rec f x = x and g = 2 in f").is_err());
+        assert!(run_code("let rec f x = x and in f").is_err());
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(eval("let ( +++ ) a b = a + b + b in 1 +++ 2"), "int = 5");
+        assert_eq!(
+            eval("let ( |-> ) x f = f x;; 3 |-> (fun x -> x * x)"),
+            "int = 9"
+        );
+        assert_eq!(eval("let ( !! ) x = x * 2 in !! 4 + 1"), "int = 9");
+        assert_eq!(eval("let ( ! ) x = x * 2 in ! 4 + 1"), "int = 9");
+        assert_eq!(eval("let ( ! ) x = 1 - x in !(!5) + !0"), "int = 6");
+        assert_eq!(eval("let ( ! ) = fun x -> [x];; ( ! ) 1"), "int list = [1]");
+        assert!(run_code("!1").is_err());
+        assert_eq!(eval("( + )"), "int -> int -> int = <fun>");
+        assert_eq!(eval("( <= )"), "'a -> 'a -> bool = <fun>");
+        assert_eq!(eval("( * ) 6 7"), "int = 42");
+        assert_eq!(eval("( ~- ) 3"), "int = -3");
+        assert_eq!(
+            eval(
+                "let rec fold f acc l = match l with [] -> acc | x :: xs -> fold f (f acc x) xs
+                 in (fold ( + ) 0 [1; 2; 3], fold ( *. ) 1. [2.; 4.])"
+            ),
+            "int * float = (6, 8.)"
+        );
+        assert_eq!(
+            eval("let rec ( ^^ ) x n = if n = 0 then 1 else x * (x ^^ (n - 1)) in 2 ^^ 10"),
+            "int = 1024"
+        );
+        assert_eq!(
+            eval("let ( +++ ) a b = a + b;; let f = ( +++ ) 1;; f 2"),
+            "int = 3"
+        );
+
+        // precedence and associativity by the first characters
+        let ops = "let ( ** ) a b = a - b
+let ( -- ) a b = a - b
+let ( >>= ) a b = a + b
+let ( %% ) a b = a - b;;";
+        let eval_ops = |src: &str| eval(&format!("{} {}", ops, src));
+        assert_eq!(eval_ops("2 ** 3 ** 2 + 1"), "int = 2");
+        assert_eq!(eval_ops("2 * 3 ** 1"), "int = 4");
+        assert_eq!(eval_ops("10 -- 3 -- 2"), "int = 5");
+        assert_eq!(eval_ops("10 %% 3 * 2"), "int = 14");
+        assert_eq!(eval_ops("1 >>= 2 * 3 = 7"), "bool = true");
+        assert_eq!(eval_ops("(fun x -> x + 1) @@ 2 * 3"), "int = 7");
+        assert_eq!(eval_ops("1 + 2 |> (fun x -> x * 3) = 9"), "bool = true");
+        assert_eq!(eval_ops("1 :: [2] @ [3] = [1; 2; 3]"), "bool = true");
+
+        // built-in operators
+        assert_eq!(eval("true && false || 1 < 2 && 2 < 3"), "bool = true");
+        assert_eq!(eval("false && 1 / 0 = 0"), "bool = false");
+        assert_eq!(eval("true || 1 / 0 = 0"), "bool = true");
+        assert_eq!(eval("7 / 2 * 2 - 7 / -2"), "int = 9");
+        assert_eq!(eval("\"a\" ^ \"b\" ^ \"c\""), "string = \"abc\"");
+        assert_eq!(
+            eval("3 |> (fun x -> x * x) |> (fun x -> x + 1)"),
+            "int = 10"
+        );
+        assert_eq!(eval("(fun x -> x + 1) @@ (fun x -> x * 2) @@ 3"), "int = 7");
+        assert_eq!(eval("( && )"), "bool -> bool -> bool = <fun>");
+        assert_eq!(eval("( || ) false true"), "bool = true");
+        assert_eq!(eval("( / ) 9 3"), "int = 3");
+        assert_eq!(eval("( ^ ) \"x\" \"y\""), "string = \"xy\"");
+        assert_eq!(eval("( |> )"), "'a -> ('a -> 'b) -> 'b = <fun>");
+        assert_eq!(eval("( @@ )"), "('a -> 'b) -> 'a -> 'b = <fun>");
+        let err = run_code("1 / 0").unwrap_err();
+        assert!(err.to_string().contains("Division_by_zero"));
+        assert!(run_code("1 && true").is_err());
+        assert!(run_code("1 ^ 2").is_err());
+        let err = run_code("1 == 1").unwrap_err();
+        assert!(err.to_string().contains("`==` is not supported, use `=`"));
+        let err = run_code("if 1 != 2 then 1 else 0").unwrap_err();
+        assert!(err.to_string().contains("`!=` is not supported, use `<>`"));
+        assert!(run_code("( == )").is_err());
+
+        let err = run_code("let ( + ) a b = a - b in 1 + 1").unwrap_err();
+        assert!(err.to_string().contains("cannot be redefined"));
+        assert!(run_code("1 +++ 2").is_err());
+        assert!(run_code("( +++ 1)").is_err());
+        assert!(run_code("let ( +++ ) = 1 in 1 +++ 2").is_err());
+    }
+
+    #[test]
+    fn test_variants() {
+        let shapes = "type shape = Circle of int | Square of int | Empty
+let area s = match s with Circle r -> 3 * r * r | Square a -> a * a | Empty -> 0;;";
+        assert_eq!(
+            eval(&format!("{} area (Circle 2) + area (Square 3)", shapes)),
+            "int = 21"
+        );
+        assert_eq!(eval(&format!("{} area", shapes)), "shape -> int = <fun>");
+        assert_eq!(
+            eval(&format!("{} [Circle 1; Empty; Square (-2)]", shapes)),
+            "shape list = [Circle 1; Empty; Square (-2)]"
+        );
+        assert_eq!(
+            eval(&format!(
+                "{} (Circle 1 = Circle 1, Empty < Circle 5, Circle 1 < Circle 2)",
+                shapes
+            )),
+            "bool * bool * bool = (true, true, true)"
+        );
+        assert_eq!(
+            eval("type t = A of int | B | C of bool | D;; (B < D, D < A 0, A 5 < C false)"),
+            "bool * bool * bool = (true, true, true)"
+        );
+
+        let tree = "type tree = Leaf | Node of tree * int * tree
+let rec insert x t = match t with
+  | Leaf -> Node (Leaf, x, Leaf)
+  | Node (l, y, r) -> if x < y then Node (insert x l, y, r) else Node (l, y, insert x r)
+let rec elements t = match t with
+  | Leaf -> []
+  | Node (l, x, r) -> elements l @ x :: elements r;;";
+        assert_eq!(
+            eval(&format!(
+                "{} elements (insert 2 (insert 3 (insert 1 Leaf)))",
+                tree
+            )),
+            "int list = [1; 2; 3]"
+        );
+        assert_eq!(
+            eval(&format!("{} insert 1 Leaf", tree)),
+            "tree = Node (Leaf, 1, Leaf)"
+        );
+        assert_eq!(
+            eval("type num = Zero | Succ of num;; Succ (Succ Zero)"),
+            "num = Succ (Succ Zero)"
+        );
+        assert_eq!(
+            eval("type t = | A of int list | B;; let f (x : t) : int = match x with A (y :: _) -> y | _ -> 0;; f (A [4])"),
+            "int = 4"
+        );
+
+        let err = run_code("type t = A | B;; A + 1").unwrap_err();
+        assert!(err.to_string().contains("Typecheck Error"));
+        let err = run_code("C 1").unwrap_err();
+        assert!(err.to_string().contains("constructor C is not declared"));
+        let err = run_code("type t = A of u").unwrap_err();
+        assert!(err.to_string().contains("type u is not declared"));
+        assert!(run_code("fun (x : shape) -> x").is_err());
+        assert!(run_code("type t = A of int;; A").is_err());
+        assert!(run_code("type t = A;; A 1").is_err());
+        assert!(run_code("type t = A of int;; A true").is_err());
+        assert!(run_code("type t = A of int;; match A 1 with A b -> b | A -> 0").is_err());
+        assert!(run_code("type t = a | B").is_err());
+        assert!(run_code("type int = A").is_err());
+        let err = run_code("type t = A;; let x = A;; type t = B;; x = B").unwrap_err();
+        assert!(err.to_string().contains("type t is already declared"));
+        let err = run_code("type t = A;; type u = B | A").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("constructor A is already declared"));
+        assert!(run_code("type t = A of int | A").is_err());
+        // all constructors of `t` stay bound, so `t` stays declared
+        assert!(run_code("type t = A;; let x = A;; type u = A;; type t = B;; x = B").is_err());
+        // like `let`, a type declaration must be ended with `;;` before an expression
+        for src in ["type t = T of t list 1", "type t = A | B A"] {
+            let err = run_code(src).unwrap_err();
+            assert!(err
+                .to_string()
+                .contains("must start a new phrase with `;;`"));
+        }
+        assert_eq!(eval("type t = A | B\nlet x = B;; x"), "t = B");
+    }
 }
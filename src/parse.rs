@@ -4,35 +4,178 @@
 
 #![allow(non_camel_case_types)]
 
-use crate::error::{ParserError, Result};
-use crate::lex::{Constant, Token};
+use crate::error::{Error, ParserError, Result};
+use crate::lex::{Constant, Span, SpannedToken, Token};
+use std::cell::Cell;
+use std::fmt::{self, Display};
+use std::rc::Rc;
 
-pub fn parse(l: Vec<Token>) -> Result<(exp, Vec<Token>)> {
-    return exp(l);
+/// Parses a single expression
+pub fn parse(l: Vec<Token>) -> Result<exp> {
+    // doc comments are not attached to the AST (yet), and the invalid input
+    // of `ERROR` tokens was already reported by `lex_recovering`
+    let l: Vec<Token> = l
+        .into_iter()
+        .filter(|t| !matches!(t, Token::DOC(_) | Token::ERROR(_)))
+        .collect();
+    let mut parser = Parser::new(&l, None);
+    let e = parser.seq()?;
+    parser.end()?;
+    Ok(e)
+}
+
+/// Like `parse`, but every node of the expression is wrapped in `exp::Loc`
+/// with the source span it was parsed from.
+pub fn parse_spanned(l: Vec<SpannedToken>) -> Result<exp> {
+    let (l, spans) = unzip_spanned(l);
+    let mut parser = Parser::new(&l, Some(&spans));
+    let e = parser.seq()?;
+    parser.end()?;
+    Ok(e)
+}
+
+/// Parses a program: top-level declarations and expressions, separated by
+/// `;;`. Expressions are located like in `parse_spanned`.
+pub fn parse_program(l: Vec<SpannedToken>) -> Result<Vec<phrase>> {
+    let (l, spans) = unzip_spanned(l);
+    Parser::new(&l, Some(&spans)).program()
+}
+
+fn unzip_spanned(l: Vec<SpannedToken>) -> (Vec<Token>, Vec<Span>) {
+    l.into_iter()
+        .filter(|t| !matches!(t.token, Token::DOC(_) | Token::ERROR(_)))
+        .map(|t| (t.token, t.span))
+        .unzip()
+}
+
+/// Number of times the parser looks at the remaining tokens while parsing
+/// `l`, which grows linearly with the length of `l`
+#[cfg(test)]
+pub(crate) fn parse_steps(l: &[Token]) -> Result<usize> {
+    let mut parser = Parser::new(l, None);
+    parser.exp()?;
+    Ok(parser.steps.get())
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum ty {
     Bool,
     Int,
+    Float,
+    String,
+    Char,
+    Unit,
     Arrow(Box<ty>, Box<ty>),
+    Tuple(Vec<ty>),
+    List(Box<ty>),
+    /// type variable, solved during type inference
+    Var(usize),
+    /// polymorphic type of a let-bound variable, generalized over the variables
+    Forall(Vec<usize>, Box<ty>),
+    /// variant type declared by `type`
+    Named(String),
 }
 
-#[derive(Debug, Clone)]
+impl Display for ty {
+    /// OCaml notation, e.g. `int * bool -> 'a`. Type variables are named
+    /// 'a, 'b, ... in the order they appear.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut vars = vec![];
+        self.vars(&mut vars);
+        f.write_str(&self.show(&vars, 0))
+    }
+}
+
+impl ty {
+    /// Type variables in order of appearance
+    fn vars(&self, vars: &mut Vec<usize>) {
+        match self {
+            ty::Var(n) if !vars.contains(n) => vars.push(*n),
+            ty::Arrow(t1, t2) => {
+                t1.vars(vars);
+                t2.vars(vars);
+            }
+            ty::Tuple(ts) => ts.iter().for_each(|t| t.vars(vars)),
+            ty::List(t) | ty::Forall(_, t) => t.vars(vars),
+            _ => {}
+        }
+    }
+
+    /// `prec` is 1 for the left side of an arrow, 2 inside of a tuple and 3
+    /// before `list`
+    fn show(&self, vars: &[usize], prec: u8) -> String {
+        let (s, p) = match self {
+            ty::Bool => ("bool".to_string(), 3),
+            ty::Int => ("int".to_string(), 3),
+            ty::Float => ("float".to_string(), 3),
+            ty::String => ("string".to_string(), 3),
+            ty::Char => ("char".to_string(), 3),
+            ty::Unit => ("unit".to_string(), 3),
+            ty::Named(name) => (name.to_string(), 3),
+            ty::Var(n) => {
+                let i = vars.iter().position(|m| m == n).unwrap_or(*n);
+                let letter = (b'a' + (i % 26) as u8) as char;
+                match i / 26 {
+                    0 => (format!("'{}", letter), 3),
+                    k => (format!("'{}{}", letter, k), 3),
+                }
+            }
+            ty::Arrow(t1, t2) => (format!("{} -> {}", t1.show(vars, 1), t2.show(vars, 0)), 0),
+            ty::Tuple(ts) => {
+                let ts: Vec<String> = ts.iter().map(|t| t.show(vars, 2)).collect();
+                (ts.join(" * "), 1)
+            }
+            ty::List(t) => (format!("{} list", t.show(vars, 3)), 3),
+            ty::Forall(_, t) => return t.show(vars, prec),
+        };
+        match p < prec {
+            true => format!("({})", s),
+            false => s,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::enum_variant_names)]
 pub enum con {
     Bcon(bool),
     Icon(isize),
+    Fcon(f64),
+    Scon(String),
+    Ccon(char),
+    /// `()`
+    Ucon,
 }
 #[derive(Debug, Clone)]
 pub enum operator {
     Add,
     Sub,
     Mul,
+    FAdd,
+    FSub,
+    FMul,
+    FDiv,
+    /// integer division `/`
+    Div,
+    /// string concatenation `^`
+    Concat,
     Leq,
     Geq,
     Eq,
+    Neq,
     Lt,
     Gt,
+    Cons,
+    Append,
+}
+
+/// Prefix operator
+#[derive(Debug, Clone)]
+pub enum unary {
+    /// `-e` or `~-e`
+    Neg,
+    /// `-.e` or `~-.e`
+    FNeg,
 }
 
 pub type var = String;
@@ -42,197 +185,1280 @@ pub enum exp {
     Var(var),
     Con(con),
     Oapp(operator, Box<exp>, Box<exp>),
+    Uapp(unary, Box<exp>),
     Fapp(Box<exp>, Box<exp>),
     If(Box<exp>, Box<exp>, Box<exp>),
-    Lam(var, Box<exp>),
-    Lamty(var, ty, Box<exp>),
+    /// Function bodies are shared with the closures they evaluate to
+    Lam(var, Rc<exp>),
+    Lamty(var, ty, Rc<exp>),
     Let(var, Box<exp>, Box<exp>),
-    Letrec(var, var, Box<exp>, Box<exp>),
-    Letrecty(var, var, ty, ty, Box<exp>, Box<exp>),
+    Letrec(var, var, Rc<exp>, Box<exp>),
+    Letrecty(var, var, ty, ty, Rc<exp>, Box<exp>),
+    /// `let rec f x = e1 and g y = e2 ... in e`, each function with its
+    /// declared type `t1 -> t2` if annotated
+    Letrecand(Vec<recursive>, Box<exp>),
+    Loc(Span, Box<exp>),
+    Tuple(Vec<exp>),
+    Letpat(pattern, Box<exp>, Box<exp>),
+    /// `[e1; ...; en]`
+    List(Vec<exp>),
+    /// `match e with p1 -> e1 | ... | pn -> en`
+    Match(Box<exp>, Vec<(pattern, exp)>),
+    /// `(e : t)`
+    Annot(Box<exp>, ty),
+    /// `e1; e2`
+    Seq(Box<exp>, Box<exp>),
+    /// constructor `C` or `C e` of a variant type
+    Constr(String, Option<Box<exp>>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum pattern {
+    Var(var),
+    Wildcard,
+    Con(con),
+    Tuple(Vec<pattern>),
+    /// `[p1; ...; pn]`
+    List(Vec<pattern>),
+    /// `p1 :: p2`
+    Cons(Box<pattern>, Box<pattern>),
+    /// `C` or `C p`
+    Constr(String, Option<Box<pattern>>),
+}
+
+/// Top-level phrase of a program
+#[derive(Debug, Clone)]
+pub enum phrase {
+    /// `let p = e`; `let rec f x = e` declares f as `let rec f x = e in f`
+    Let(pattern, exp),
+    Exp(exp),
+    /// `type t = C1 of t1 | ... | Cn`, the constructors with their arguments
+    Type(String, Vec<(String, Option<ty>)>),
+}
+
+impl exp {
+    /// The expression without its `exp::Loc` wrappers
+    pub fn unlocated(self) -> exp {
+        match self {
+            exp::Loc(_, e) => e.unlocated(),
+            e => e,
+        }
+    }
+
+    /// Source span of a located expression
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            exp::Loc(span, _) => Some(*span),
+            _ => None,
+        }
+    }
+}
+
+/// Hint for a token that cannot start or continue an expression
+fn stray(t: &Token) -> Option<String> {
+    match t {
+        Token::RP => Some("there is a `)` without matching `(`".to_string()),
+        Token::RB => Some("there is a `]` without matching `[`".to_string()),
+        Token::IN => Some("there is an `in` without a `let`".to_string()),
+        Token::THEN => Some("there is a `then` without `if`".to_string()),
+        Token::ELSE => Some("there is an `else` without `if`".to_string()),
+        Token::WITH => Some("there is a `with` without `match`".to_string()),
+        Token::END => Some("there is an `end` without `begin`".to_string()),
+        Token::PEQ => Some("physical equality `==` is not supported, use `=`".to_string()),
+        Token::PNEQ => Some("physical inequality `!=` is not supported, use `<>`".to_string()),
+        _ => None,
+    }
 }
 
-fn verify(token: Token, tokenlist: Vec<Token>) -> Result<Vec<Token>> {
-    match tokenlist.as_slice() {
-        [] => Err(ParserError::NoToken)?,
-        [t, l @ ..] => {
-            if *t == token {
-                Ok(l.to_vec())
-            } else {
-                Err(ParserError::WrongToken)?
+/// Whether `t` can start the argument of a function application
+fn starts_argument(t: &Token) -> bool {
+    matches!(
+        t,
+        Token::CON(_)
+            | Token::VAR(_)
+            | Token::UIDENT(_)
+            | Token::PREFIXOP(_)
+            | Token::BANG
+            | Token::LP
+            | Token::LB
+            | Token::BEGIN
+    )
+}
+
+/// Precedence levels of infix operators, from the loosest to the tightest
+#[derive(PartialEq)]
+enum level {
+    Or,
+    And,
+    Compare,
+    Concat,
+    Cons,
+    Add,
+    Mul,
+    Power,
+}
+
+/// Infix operator of an expression
+enum infix {
+    Builtin(operator),
+    /// `e1 && e2`, which only evaluates `e2` if `e1` is true
+    And,
+    /// `e1 || e2`, which only evaluates `e2` if `e1` is false
+    Or,
+    /// `x |> f`, applying `f` to `x`
+    Pipe,
+    /// `f @@ x`, applying `f` to `x`
+    Apply,
+    /// operator without built-in meaning, applying the function it names
+    Named(var),
+}
+
+/// `e1 o e2`; an operator without built-in meaning applies the function it
+/// names: `a +++ b` is `( +++ ) a b`
+fn infix_exp(o: infix, e1: exp, e2: exp) -> exp {
+    let (e1, e2) = (Box::new(e1), Box::new(e2));
+    match o {
+        infix::Builtin(o) => exp::Oapp(o, e1, e2),
+        infix::And => exp::If(e1, e2, Box::new(exp::Con(con::Bcon(false)))),
+        infix::Or => exp::If(e1, Box::new(exp::Con(con::Bcon(true))), e2),
+        infix::Pipe => exp::Fapp(e2, e1),
+        infix::Apply => exp::Fapp(e1, e2),
+        infix::Named(name) => exp::Fapp(Box::new(exp::Fapp(Box::new(exp::Var(name)), e1)), e2),
+    }
+}
+
+/// Precedence and meaning of the infix operator `t`. Like in OCaml, the
+/// precedence of an operator without built-in meaning is given by its first
+/// characters.
+fn infix_operator(t: &Token) -> Option<(level, infix)> {
+    let (l, o) = match t {
+        Token::EQ => (level::Compare, operator::Eq),
+        Token::NEQ => (level::Compare, operator::Neq),
+        Token::LT => (level::Compare, operator::Lt),
+        Token::GT => (level::Compare, operator::Gt),
+        Token::LEQ => (level::Compare, operator::Leq),
+        Token::GEQ => (level::Compare, operator::Geq),
+        Token::APPEND => (level::Concat, operator::Append),
+        Token::CONS => (level::Cons, operator::Cons),
+        Token::ADD => (level::Add, operator::Add),
+        Token::SUB => (level::Add, operator::Sub),
+        Token::FADD => (level::Add, operator::FAdd),
+        Token::FSUB => (level::Add, operator::FSub),
+        Token::MUL => (level::Mul, operator::Mul),
+        Token::FMUL => (level::Mul, operator::FMul),
+        Token::FDIV => (level::Mul, operator::FDiv),
+        Token::DIV => (level::Mul, operator::Div),
+        Token::CONCAT => (level::Concat, operator::Concat),
+        Token::OR => return Some((level::Or, infix::Or)),
+        Token::AND => return Some((level::And, infix::And)),
+        Token::PIPE => return Some((level::Compare, infix::Pipe)),
+        Token::APPLY => return Some((level::Concat, infix::Apply)),
+        Token::INFIXOP(_) => return named(t),
+        _ => return None,
+    };
+    Some((l, infix::Builtin(o)))
+}
+
+/// Precedence of the operator `t` without built-in meaning
+fn named(t: &Token) -> Option<(level, infix)> {
+    let op = t.to_string();
+    let l = match op.chars().next()? {
+        _ if op == "&" => level::And,
+        '*' if op.starts_with("**") => level::Power,
+        '*' | '/' | '%' => level::Mul,
+        '+' | '-' => level::Add,
+        '@' | '^' => level::Concat,
+        '=' | '<' | '>' | '|' | '&' | '$' | '!' => level::Compare,
+        _ => return None,
+    };
+    Some((l, infix::Named(op)))
+}
+
+/// Prefix operator `t`: `!` or a `PREFIXOP` such as `!!` or `~-`
+fn prefix_operator(t: &Token) -> Option<&str> {
+    match t {
+        Token::BANG => Some("!"),
+        Token::PREFIXOP(op) => Some(op),
+        _ => None,
+    }
+}
+
+/// Whether `t` can be written as a function `( t )`
+fn is_operator(t: &Token) -> bool {
+    infix_operator(t).is_some() || prefix_operator(t).is_some()
+}
+
+/// Name of the function `( t )` if users can define it, that is if `t` has no
+/// built-in meaning
+fn operator_name(t: &Token) -> Option<var> {
+    match prefix_operator(t) {
+        Some("~-" | "~-.") => None,
+        Some(op) => Some(op.to_string()),
+        None => match infix_operator(t)? {
+            (_, infix::Named(op)) => Some(op),
+            (_, _) => None,
+        },
+    }
+}
+
+/// The operator `t` as a function `( t )`: `( + )` is `fun x y -> x + y`
+fn section(t: &Token) -> Option<exp> {
+    let (x, y) = ("%x".to_string(), "%y".to_string());
+    let e = match prefix_operator(t) {
+        Some(op @ ("~-" | "~-.")) => {
+            let o = match op {
+                "~-" => unary::Neg,
+                _ => unary::FNeg,
+            };
+            let e = exp::Uapp(o, Box::new(exp::Var(x.clone())));
+            return Some(exp::Lam(x, Rc::new(e)));
+        }
+        Some(op) => return Some(exp::Var(op.to_string())),
+        None => match infix_operator(t)? {
+            (_, infix::Named(op)) => return Some(exp::Var(op)),
+            (_, o) => infix_exp(o, exp::Var(x.clone()), exp::Var(y.clone())),
+        },
+    };
+    Some(exp::Lam(x, Rc::new(exp::Lam(y, Rc::new(e)))))
+}
+
+/// Function of a `let rec`: its name, parameter, declared type `t1 -> t2` if
+/// it is fully annotated, and body
+type recursive = (var, var, Option<(ty, ty)>, Rc<exp>);
+
+/// `let x = e` or `let rec f params : result = e and ...` before its `in`
+enum Binding {
+    Let(var, exp),
+    Pattern(pattern, exp),
+    Letrec(Vec<recursive>),
+}
+
+impl Binding {
+    /// `let ... in e2`
+    fn scope(self, e2: exp) -> exp {
+        match self {
+            Binding::Let(x, e1) => exp::Let(x, Box::new(e1), Box::new(e2)),
+            Binding::Pattern(p, e1) => exp::Letpat(p, Box::new(e1), Box::new(e2)),
+            Binding::Letrec(functions) => letrec(functions, e2),
+        }
+    }
+
+    /// The binding as a top-level declaration. Mutually recursive functions
+    /// are declared as a tuple: `let rec f x = e1 and g y = e2` is
+    /// `let (f, g) = let rec f x = e1 and g y = e2 in (f, g)`.
+    fn declaration(self) -> phrase {
+        match self {
+            Binding::Let(x, e) => phrase::Let(pattern::Var(x), e),
+            Binding::Pattern(p, e) => phrase::Let(p, e),
+            Binding::Letrec(functions) => {
+                let mut names: Vec<var> = functions.iter().map(|(f, ..)| f.clone()).collect();
+                match names.len() {
+                    1 => {
+                        let f = names.remove(0);
+                        phrase::Let(pattern::Var(f.clone()), letrec(functions, exp::Var(f)))
+                    }
+                    _ => {
+                        let p = pattern::Tuple(names.iter().cloned().map(pattern::Var).collect());
+                        let e = exp::Tuple(names.into_iter().map(exp::Var).collect());
+                        phrase::Let(p, letrec(functions, e))
+                    }
+                }
             }
         }
     }
 }
 
-fn ty(l: Vec<Token>) -> Result<(ty, Vec<Token>)> {
-    let (t, l) = pty(l)?;
-    return ty2(t, l);
+/// Curried function taking `params`: `fun x y -> e` is `fun x -> fun y -> e`
+fn lambda(params: Vec<(pattern, Option<ty>)>, e: exp) -> exp {
+    params.into_iter().rev().fold(e, |e, (p, t)| {
+        let (x, e) = parameter(p, e);
+        match t {
+            Some(t) => exp::Lamty(x, t, Rc::new(e)),
+            None => exp::Lam(x, Rc::new(e)),
+        }
+    })
+}
 
-    fn ty2(t1: ty, l: Vec<Token>) -> Result<(ty, Vec<Token>)> {
-        match l.as_slice() {
-            [Token::ARR, l @ ..] => {
-                let (t2, l) = pty(l.to_vec())?;
-                let (t, l) = ty2(t2, l)?;
-                return Ok((ty::Arrow(Box::new(t1), Box::new(t)), l));
+/// Variable for the parameter `p` of a function with body `e`. Patterns are
+/// matched against an unnameable variable: `fun (a, b) -> e` is
+/// `fun %arg -> let (a, b) = %arg in e`.
+fn parameter(p: pattern, e: exp) -> (var, exp) {
+    match p {
+        pattern::Var(x) => (x, e),
+        p => {
+            let x = "%arg".to_string();
+            let e = exp::Letpat(p, Box::new(exp::Var(x.clone())), Box::new(e));
+            (x, e)
+        }
+    }
+}
+
+/// `let rec f params : result = e1`, where `params` is not empty. If only
+/// some of the parameters and the result are annotated, the annotations are
+/// checked on the body: `let rec f (x:t) = e1` is
+/// `let rec f x = let x = (x : t) in e1`.
+fn recursive_function(
+    f: var,
+    params: Vec<(pattern, Option<ty>)>,
+    result: Option<ty>,
+    e1: exp,
+) -> recursive {
+    let mut params = params.into_iter();
+    let (p, t1) = params.next().expect("let rec without parameter");
+    let rest: Vec<(pattern, Option<ty>)> = params.collect();
+    let declared = match (&t1, &result) {
+        (Some(t1), Some(result)) => rest
+            .iter()
+            .rev()
+            .try_fold(result.clone(), |t, (_, tx)| {
+                Some(ty::Arrow(Box::new(tx.clone()?), Box::new(t)))
+            })
+            .map(|t2| (t1.clone(), t2)),
+        _ => None,
+    };
+    if declared.is_some() {
+        let (x, e1) = parameter(p, lambda(rest, e1));
+        return (f, x, declared, Rc::new(e1));
+    }
+    let e1 = match result {
+        Some(t) => exp::Annot(Box::new(e1), t),
+        None => e1,
+    };
+    let (x, e1) = parameter(p, lambda(rest, e1));
+    let e1 = match t1 {
+        Some(t) => {
+            let annotated = exp::Annot(Box::new(exp::Var(x.clone())), t);
+            exp::Let(x.clone(), Box::new(annotated), Box::new(e1))
+        }
+        None => e1,
+    };
+    (f, x, None, Rc::new(e1))
+}
+
+/// `let rec ... in e2` defining `functions`, which is not empty
+fn letrec(mut functions: Vec<recursive>, e2: exp) -> exp {
+    if functions.len() > 1 {
+        return exp::Letrecand(functions, Box::new(e2));
+    }
+    match functions.remove(0) {
+        (f, x, Some((t1, t2)), e1) => exp::Letrecty(f, x, t1, t2, e1, Box::new(e2)),
+        (f, x, None, e1) => exp::Letrec(f, x, e1, Box::new(e2)),
+    }
+}
+
+/// Recursive descent parser over a borrowed token slice. Every grammar
+/// method parses from the current position `pos` and leaves it after the
+/// last token it consumed.
+struct Parser<'a> {
+    tokens: &'a [Token],
+    /// spans of the tokens, `None` if parsing without locations
+    spans: Option<&'a [Span]>,
+    pos: usize,
+    /// number of lookups of the remaining tokens so far
+    steps: Cell<usize>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(tokens: &'a [Token], spans: Option<&'a [Span]>) -> Self {
+        Parser {
+            tokens,
+            spans,
+            pos: 0,
+            steps: Cell::new(0),
+        }
+    }
+
+    /// Tokens not consumed yet
+    fn rest(&self) -> &'a [Token] {
+        self.steps.set(self.steps.get() + 1);
+        &self.tokens[self.pos..]
+    }
+
+    fn peek(&self) -> Option<&'a Token> {
+        self.steps.set(self.steps.get() + 1);
+        self.tokens.get(self.pos)
+    }
+
+    /// Consumes `n` tokens
+    fn bump(&mut self, n: usize) {
+        self.pos += n;
+    }
+
+    /// Span of the token at `pos`
+    fn span_at(&self, pos: usize) -> Option<Span> {
+        self.spans?.get(pos).copied()
+    }
+
+    /// Hint for a `delimiter` at position `open` that is not closed
+    fn unclosed(&self, open: usize, delimiter: &str) -> String {
+        match self.span_at(open) {
+            Some(span) => format!("the `{}` at {} is never closed", delimiter, span),
+            None => format!("a `{}` is never closed", delimiter),
+        }
+    }
+
+    /// Error for the current token, which is not what was `expected`
+    fn error(&self, expected: &str, hint: Option<String>) -> Error {
+        self.error_at(self.pos, expected, hint)
+    }
+
+    /// Error for the token at `pos`, which is not what was `expected`
+    fn error_at(&self, pos: usize, expected: &str, hint: Option<String>) -> Error {
+        match self.tokens.get(pos) {
+            Some(t) => ParserError::WrongToken {
+                expected: expected.to_string(),
+                found: format!("`{}`", t),
+                span: self.span_at(pos),
+                hint,
+            },
+            None => ParserError::NoToken {
+                expected: expected.to_string(),
+                hint,
+            },
+        }
+        .into()
+    }
+
+    /// Consumes `token`, which is expected `context` (e.g. "after the let-binding")
+    fn verify(&mut self, token: Token, context: &str) -> Result<()> {
+        match self.peek() {
+            Some(t) if *t == token => {
+                self.bump(1);
+                Ok(())
+            }
+            found => {
+                let hint = match (&token, found) {
+                    (Token::ELSE, _) => {
+                        Some("`if ... then ...` must have an `else` branch".to_string())
+                    }
+                    (Token::IN, None) => Some(
+                        "`let x = e` must be followed by `in` and the expression using x"
+                            .to_string(),
+                    ),
+                    (Token::ARR, Some(Token::EQ)) => {
+                        Some("`fun` uses `->` instead of `=`".to_string())
+                    }
+                    _ => None,
+                };
+                Err(self.error(&format!("`{}` {}", token, context), hint))
+            }
+        }
+    }
+
+    /// Consumes the `)` closing the `(` at position `open`
+    fn close(&mut self, open: usize) -> Result<()> {
+        match self.peek() {
+            Some(Token::RP) => {
+                self.bump(1);
+                Ok(())
+            }
+            _ => Err(self.error("`)`", Some(self.unclosed(open, "(")))),
+        }
+    }
+
+    /// Parameters of a function: `x`, `_`, `()`, `(p)` or `(p:t)`, possibly none
+    fn params(&mut self) -> Result<Vec<(pattern, Option<ty>)>> {
+        let mut params = vec![];
+        loop {
+            match self.peek() {
+                Some(Token::VAR(x)) => {
+                    self.bump(1);
+                    params.push((pattern::Var(x.to_string()), None));
+                }
+                Some(Token::UNDERSCORE) => {
+                    self.bump(1);
+                    params.push((pattern::Wildcard, None));
+                }
+                Some(Token::LP) if self.rest().get(1) == Some(&Token::RP) => {
+                    self.bump(2);
+                    params.push((pattern::Con(con::Ucon), None));
+                }
+                Some(Token::LP) => {
+                    let open = self.pos;
+                    self.bump(1);
+                    let p = self.pattern()?;
+                    let t = match self.peek() {
+                        Some(Token::COL) => {
+                            self.bump(1);
+                            Some(self.ty()?)
+                        }
+                        _ => None,
+                    };
+                    self.close(open)?;
+                    params.push((p, t));
+                }
+                _ => return Ok(params),
             }
-            l => Ok((t1, l.to_vec())),
         }
     }
 
-    fn pty(l: Vec<Token>) -> Result<(ty, Vec<Token>)> {
-        match l.as_slice() {
-            [Token::VAR(x), l @ ..] => {
-                if *x == "bool".as_ref() {
-                    Ok((ty::Bool, l.to_vec()))
-                } else if *x == "int".as_ref() {
-                    Ok((ty::Int, l.to_vec()))
-                } else {
-                    Err(ParserError::TypeError)?
+    /// `p1, ..., pn`
+    fn pattern(&mut self) -> Result<pattern> {
+        let mut ps = vec![self.cpattern()?];
+        while let Some(Token::COMMA) = self.peek() {
+            self.bump(1);
+            ps.push(self.cpattern()?);
+        }
+        match ps.len() {
+            1 => Ok(ps.remove(0)),
+            _ => Ok(pattern::Tuple(ps)),
+        }
+    }
+
+    /// `p1 :: p2`, right associative
+    fn cpattern(&mut self) -> Result<pattern> {
+        let p1 = self.ppattern()?;
+        if let Some(Token::CONS) = self.peek() {
+            self.bump(1);
+            let p2 = self.cpattern()?;
+            return Ok(pattern::Cons(Box::new(p1), Box::new(p2)));
+        }
+        Ok(p1)
+    }
+
+    fn ppattern(&mut self) -> Result<pattern> {
+        let p = match self.peek() {
+            Some(Token::VAR(x)) => pattern::Var(x.to_string()),
+            Some(Token::UNDERSCORE) => pattern::Wildcard,
+            Some(Token::CON(Constant::BCON(b))) => pattern::Con(con::Bcon(*b)),
+            Some(Token::CON(Constant::ICON(n))) => pattern::Con(con::Icon(*n)),
+            Some(Token::CON(Constant::FCON(x))) => pattern::Con(con::Fcon(*x)),
+            Some(Token::CON(Constant::SCON(s))) => pattern::Con(con::Scon(s.to_string())),
+            Some(Token::CON(Constant::CCON(c))) => pattern::Con(con::Ccon(*c)),
+            Some(Token::UIDENT(c)) => {
+                let c = c.to_string();
+                self.bump(1);
+                let p = match self.peek() {
+                    Some(
+                        Token::VAR(_)
+                        | Token::UNDERSCORE
+                        | Token::CON(_)
+                        | Token::UIDENT(_)
+                        | Token::LP
+                        | Token::LB,
+                    ) => Some(Box::new(self.ppattern()?)),
+                    _ => None,
+                };
+                return Ok(pattern::Constr(c, p));
+            }
+            Some(Token::SUB) => match self.rest().get(1) {
+                Some(Token::CON(Constant::ICON(n))) => {
+                    self.bump(2);
+                    return Ok(pattern::Con(con::Icon(n.wrapping_neg())));
+                }
+                Some(Token::CON(Constant::FCON(x))) => {
+                    self.bump(2);
+                    return Ok(pattern::Con(con::Fcon(-x)));
                 }
+                _ => {
+                    self.bump(1);
+                    return Err(self.error("a number after `-` in a pattern", None));
+                }
+            },
+            Some(Token::LP) if self.rest().get(1) == Some(&Token::RP) => {
+                self.bump(2);
+                return Ok(pattern::Con(con::Ucon));
+            }
+            Some(Token::LP) => {
+                let open = self.pos;
+                self.bump(1);
+                let p = self.pattern()?;
+                self.close(open)?;
+                return Ok(p);
             }
-            [Token::LP, l @ ..] => {
-                let (t, l) = ty(l.to_vec())?;
-                return Ok((t, verify(Token::RP, l)?));
+            Some(Token::LB) => {
+                let open = self.pos;
+                self.bump(1);
+                let mut ps = vec![];
+                while self.peek() != Some(&Token::RB) {
+                    ps.push(self.pattern()?);
+                    match self.peek() {
+                        Some(Token::SEMI) => self.bump(1),
+                        Some(Token::RB) => {}
+                        _ => {
+                            let hint = self.unclosed(open, "[");
+                            return Err(self.error("`;` or `]` in the list", Some(hint)));
+                        }
+                    }
+                }
+                pattern::List(ps)
+            }
+            _ => return Err(self.error("a pattern", None)),
+        };
+        self.bump(1);
+        Ok(p)
+    }
+
+    /// `p1 -> e1 | ... | pn -> en`, with an optional `|` in front
+    fn cases(&mut self) -> Result<Vec<(pattern, exp)>> {
+        if let Some(Token::BAR) = self.peek() {
+            self.bump(1);
+        }
+        let mut cases = vec![];
+        loop {
+            let p = self.pattern()?;
+            self.verify(Token::ARR, "after the pattern")?;
+            let e = self.seq()?;
+            cases.push((p, e));
+            match self.peek() {
+                Some(Token::BAR) => self.bump(1),
+                _ => return Ok(cases),
             }
-            _ => Err(ParserError::TypeError)?,
         }
     }
-}
 
-fn exp(l: Vec<Token>) -> Result<(exp, Vec<Token>)> {
-    match l.as_slice() {
-        [Token::IF, l @ ..] => {
-            let (e1, l) = exp(l.to_vec())?;
-            let (e2, l) = (verify(Token::THEN, l)).and_then(exp)?; //(verify(Token::THEN, l)).and_then(exp)?
-            let (e3, l) = exp(verify(Token::ELSE, l)?)?;
+    fn ty(&mut self) -> Result<ty> {
+        let t1 = self.tty()?;
+        if let Some(Token::ARR) = self.peek() {
+            self.bump(1);
+            let t2 = self.ty()?;
+            return Ok(ty::Arrow(Box::new(t1), Box::new(t2)));
+        }
+        Ok(t1)
+    }
 
-            return Ok((
-                exp::If(Box::new(e1), Box::new(e2), Box::new(e3)),
-                l.to_vec(),
-            ));
+    /// `t1 * ... * tn`
+    fn tty(&mut self) -> Result<ty> {
+        let mut ts = vec![self.pty()?];
+        while let Some(Token::MUL) = self.peek() {
+            self.bump(1);
+            ts.push(self.pty()?);
         }
-        [Token::LAM, Token::VAR(x), Token::ARR, l @ ..] => {
-            let (e, l) = exp(l.to_vec())?;
+        match ts.len() {
+            1 => Ok(ts.remove(0)),
+            _ => Ok(ty::Tuple(ts)),
+        }
+    }
 
-            return Ok((exp::Lam(x.to_string(), Box::new(e)), l));
+    /// `t list`
+    fn pty(&mut self) -> Result<ty> {
+        let mut t = self.aty()?;
+        while let Some(Token::VAR(x)) = self.peek() {
+            if x != "list" {
+                break;
+            }
+            self.bump(1);
+            t = ty::List(Box::new(t));
         }
-        [Token::LAM, Token::LP, Token::VAR(x), Token::COL, l @ ..] => {
-            let (t, l) = ty(l.to_vec())?;
-            let (e, l) = exp(verify(Token::ARR, verify(Token::RP, l)?)?)?;
-            return Ok((exp::Lamty(x.to_string(), t, Box::new(e)), l));
+        Ok(t)
+    }
+
+    fn aty(&mut self) -> Result<ty> {
+        let t = match self.peek() {
+            Some(Token::VAR(x)) => match x.as_str() {
+                "bool" => ty::Bool,
+                "int" => ty::Int,
+                "float" => ty::Float,
+                "string" => ty::String,
+                "char" => ty::Char,
+                "unit" => ty::Unit,
+                "list" => return Err(self.error("a type", None)),
+                name => ty::Named(name.to_string()),
+            },
+            Some(Token::LP) => {
+                let open = self.pos;
+                self.bump(1);
+                let t = self.ty()?;
+                self.close(open)?;
+                return Ok(t);
+            }
+            _ => return Err(self.error("a type", None)),
+        };
+        self.bump(1);
+        Ok(t)
+    }
+
+    /// Wraps `e` in the span reaching from the token at `start` up to the
+    /// last token consumed
+    fn locate(&self, e: exp, start: usize) -> exp {
+        match (self.span_at(start), self.pos.checked_sub(1)) {
+            (Some(first), Some(last)) if start < self.pos => {
+                exp::Loc(first.to(self.span_at(last).unwrap_or(first)), Box::new(e))
+            }
+            _ => e,
         }
-        [Token::LET, Token::VAR(x), Token::EQ, l @ ..] => {
-            let (e1, l) = exp(l.to_vec())?;
-            let (e2, l) = exp(verify(Token::IN, l)?)?;
+    }
 
-            return Ok((exp::Let(x.to_string(), Box::new(e1), Box::new(e2)), l));
+    /// Wraps `e` in the span reaching from the start of `from` to the end of `to`
+    fn locate_between(&self, e: exp, from: Option<Span>, to: Option<Span>) -> exp {
+        match (from, to) {
+            (Some(first), Some(last)) => exp::Loc(first.to(last), Box::new(e)),
+            _ => e,
         }
-        [Token::LET, Token::REC, Token::VAR(f), Token::VAR(x), Token::EQ, l @ ..] => {
-            let (e1, l) = exp(l.to_vec())?;
-            let (e2, l) = exp(verify(Token::IN, l)?)?;
+    }
 
-            return Ok((
-                exp::Letrec(f.to_string(), x.to_string(), Box::new(e1), Box::new(e2)),
-                l,
-            ));
+    /// Consumes the infix operator at the current position if it has the
+    /// precedence `l`
+    fn infix(&mut self, l: level) -> Result<Option<infix>> {
+        match self.peek() {
+            Some(t @ (Token::PEQ | Token::PNEQ)) => {
+                Err(self.error("a supported infix operator", stray(t)))
+            }
+            t => match t.and_then(infix_operator) {
+                Some((l2, o)) if l2 == l => {
+                    self.bump(1);
+                    Ok(Some(o))
+                }
+                _ => Ok(None),
+            },
         }
-        [Token::LET, Token::REC, Token::VAR(f), Token::LP, Token::VAR(x), Token::COL, l @ ..] => {
-            let (t1, l) = ty(l.to_vec())?;
-            let (t2, l) = ty(verify(Token::COL, verify(Token::RP, l)?)?)?;
-            let (e1, l) = exp(verify(Token::EQ, l)?)?;
-            let (e2, l) = exp(verify(Token::IN, l)?)?;
+    }
+
+    /// `e1 o e2`, located from `e1` to `e2`
+    fn apply(&self, o: infix, e1: exp, e2: exp) -> exp {
+        let (from, to) = (e1.span(), e2.span());
+        self.locate_between(infix_exp(o, e1, e2), from, to)
+    }
 
-            return Ok((
-                exp::Letrecty(
-                    f.to_string(),
-                    x.to_string(),
-                    t1,
-                    t2,
-                    Box::new(e1),
-                    Box::new(e2),
-                ),
-                l,
-            ));
+    /// Name of a defined value: a variable `x` or an operator `( op )`
+    fn value_name(&mut self) -> Result<Option<var>> {
+        match self.rest() {
+            [Token::VAR(x), ..] => {
+                self.bump(1);
+                Ok(Some(x.to_string()))
+            }
+            [Token::LP, t, Token::RP, ..] => match operator_name(t) {
+                Some(name) => {
+                    self.bump(3);
+                    Ok(Some(name))
+                }
+                None if is_operator(t) => {
+                    self.bump(1);
+                    Err(self.error(
+                        "a user-defined operator",
+                        Some(format!("the built-in operator `{}` cannot be redefined", t)),
+                    ))
+                }
+                None => Ok(None),
+            },
+            _ => Ok(None),
         }
-        l => return cexp(l.to_vec()),
     }
 
-    fn cexp(l: Vec<Token>) -> Result<(exp, Vec<Token>)> {
-        let (e, l) = sexp(l.to_vec())?;
-        return cexp_la(e, l);
+    /// `e1; e2`, right associative. A `;` may also end a sequence before
+    /// `)`, `end`, `;;` or the end of the input.
+    fn seq(&mut self) -> Result<exp> {
+        let start = self.pos;
+        let e1 = self.exp()?;
+        if let Some(Token::SEMI) = self.peek() {
+            self.bump(1);
+            if let None | Some(Token::RP | Token::END | Token::SEMISEMI) = self.peek() {
+                return Ok(e1);
+            }
+            let e2 = self.seq()?;
+            return Ok(self.locate(exp::Seq(Box::new(e1), Box::new(e2)), start));
+        }
+        Ok(e1)
     }
 
-    fn cexp_la(e1: exp, l: Vec<Token>) -> Result<(exp, Vec<Token>)> {
-        match l.as_slice() {
-            [Token::LEQ, l @ ..] => {
-                let (e2, l) = sexp(l.to_vec())?;
-                return Ok((exp::Oapp(operator::Leq, Box::new(e1), Box::new(e2)), l));
+    /// `e1, ..., en`
+    fn exp(&mut self) -> Result<exp> {
+        let start = self.pos;
+        let e = self.iexp()?;
+        if let Some(Token::COMMA) = self.peek() {
+            let mut es = vec![e];
+            while let Some(Token::COMMA) = self.peek() {
+                self.bump(1);
+                es.push(self.iexp()?);
             }
-            l => Ok((e1, l.to_vec())),
+            return Ok(self.locate(exp::Tuple(es), start));
         }
+        Ok(e)
     }
 
-    fn sexp(l: Vec<Token>) -> Result<(exp, Vec<Token>)> {
-        let (e, l) = mexp(l)?;
-        return sexp_la(e, l);
+    /// Expressions binding tighter than `,`. The bodies of `let` and `fun`
+    /// reach as far as possible, including `,`.
+    fn iexp(&mut self) -> Result<exp> {
+        let start = self.pos;
+        let e = match self.rest() {
+            [Token::IF, ..] => {
+                self.bump(1);
+                let e1 = self.exp()?;
+                self.verify(Token::THEN, "after the condition")?;
+                let e2 = self.iexp()?;
+                self.verify(Token::ELSE, "after the `then` branch")?;
+                let e3 = self.iexp()?;
+
+                exp::If(Box::new(e1), Box::new(e2), Box::new(e3))
+            }
+            [Token::LAM, ..] => {
+                self.bump(1);
+                let params = self.params()?;
+                if params.is_empty() {
+                    return Err(self.error("a parameter `x` or `(p:t)` after `fun`", None));
+                }
+                self.verify(Token::ARR, "after the parameters")?;
+                let e = self.seq()?;
+
+                lambda(params, e)
+            }
+            [Token::LET, ..] => {
+                let binding = self.binding()?;
+                self.verify(Token::IN, "after the let-binding")?;
+                let e2 = self.seq()?;
+
+                binding.scope(e2)
+            }
+            [Token::MATCH, ..] => {
+                self.bump(1);
+                let e = self.exp()?;
+                self.verify(Token::WITH, "after the matched expression")?;
+                let cases = self.cases()?;
+
+                exp::Match(Box::new(e), cases)
+            }
+            _ => return self.oexp(),
+        };
+        Ok(self.locate(e, start))
     }
 
-    fn sexp_la(e1: exp, l: Vec<Token>) -> Result<(exp, Vec<Token>)> {
-        match l.as_slice() {
-            [Token::ADD, l @ ..] => {
-                let (e2, l) = mexp(l.to_vec())?;
-                return sexp_la(exp::Oapp(operator::Add, Box::new(e1), Box::new(e2)), l);
+    /// `let x = e` or `let rec f x = e and ...`, without the `in` part
+    fn binding(&mut self) -> Result<Binding> {
+        match self.rest() {
+            [Token::LET, Token::REC, ..] => {
+                self.bump(2);
+                let f = match self.value_name()? {
+                    Some(f) => f,
+                    None => return Err(self.error("a function name after `let rec`", None)),
+                };
+                let mut functions = vec![self.recursive(f)?];
+                while let Some(Token::LETAND) = self.peek() {
+                    self.bump(1);
+                    let g = match self.value_name()? {
+                        Some(g) => g,
+                        None => return Err(self.error("a function name after `and`", None)),
+                    };
+                    functions.push(self.recursive(g)?);
+                }
+                Ok(Binding::Letrec(functions))
             }
-            [Token::SUB, l @ ..] => {
-                let (e2, l) = mexp(l.to_vec())?;
-                return sexp_la(exp::Oapp(operator::Sub, Box::new(e1), Box::new(e2)), l);
+            [Token::LET, Token::VAR(_), Token::COMMA, ..] | [Token::LET, Token::UNDERSCORE, ..] => {
+                self.pattern_binding()
+            }
+            [Token::LET, Token::LP, t, Token::RP, ..] if is_operator(t) => {
+                self.bump(1);
+                let x = self.value_name()?.expect("operator name");
+                self.function_binding(x)
+            }
+            [Token::LET, Token::LP, ..] => self.pattern_binding(),
+            [Token::LET, Token::VAR(x), ..] => {
+                self.bump(2);
+                self.function_binding(x.to_string())
+            }
+            _ => {
+                self.bump(1);
+                Err(self.error("a variable or a pattern after `let`", None))
             }
-            l => Ok((e1, l.to_vec())),
         }
     }
 
-    fn mexp(l: Vec<Token>) -> Result<(exp, Vec<Token>)> {
-        let (e, l) = aexp(l)?;
-        return mexp_la(e, l);
+    /// `let p = e`
+    fn pattern_binding(&mut self) -> Result<Binding> {
+        self.bump(1);
+        let p = self.pattern()?;
+        self.verify(Token::EQ, "after the pattern")?;
+        let e = self.seq()?;
+        Ok(Binding::Pattern(p, e))
     }
 
-    fn mexp_la(e1: exp, l: Vec<Token>) -> Result<(exp, Vec<Token>)> {
-        match l.as_slice() {
-            [Token::MUL, l @ ..] => {
-                let (e2, l) = aexp(l.to_vec())?;
-                return mexp_la(exp::Oapp(operator::Mul, Box::new(e1), Box::new(e2)), l);
+    /// `let x params : result = e`, after the name `x`
+    fn function_binding(&mut self, x: var) -> Result<Binding> {
+        let params = self.params()?;
+        let context = match params.is_empty() {
+            true => "after the variable",
+            false => "after the parameters",
+        };
+        let result = match self.peek() {
+            Some(Token::COL) => {
+                self.bump(1);
+                Some(self.ty()?)
             }
-            l => Ok((e1, l.to_vec())),
+            _ => None,
+        };
+        self.verify(Token::EQ, context)?;
+        let e = self.seq()?;
+        let e = match result {
+            Some(t) => exp::Annot(Box::new(e), t),
+            None => e,
+        };
+        Ok(Binding::Let(x, lambda(params, e)))
+    }
+
+    /// `f params : result = e` of a `let rec`, after the name `f`
+    fn recursive(&mut self, f: var) -> Result<recursive> {
+        let mut params = self.params()?;
+        let mut result = match self.peek() {
+            Some(Token::COL) => {
+                self.bump(1);
+                Some(self.ty()?)
+            }
+            _ => None,
+        };
+        self.verify(Token::EQ, "in the definition of the function")?;
+        let body = self.pos;
+        let mut e = self.seq()?;
+        if params.is_empty() {
+            // `let rec f = fun x -> e`
+            let (x, tx, inner) = match e.unlocated() {
+                exp::Lam(x, e) => (pattern::Var(x), None, Rc::unwrap_or_clone(e)),
+                exp::Lamty(x, t, e) => (pattern::Var(x), Some(t), Rc::unwrap_or_clone(e)),
+                _ => {
+                    return Err(self.error_at(
+                        body,
+                        "a function after `let rec`",
+                        Some("`let rec` can only define functions".to_string()),
+                    ))
+                }
+            };
+            // without parameters, `let rec f : t1 -> t2` annotates the
+            // whole function: `x` has type `t1` and the body `t2`
+            let tx = match (result.take(), tx) {
+                (None, tx) => tx,
+                (Some(ty::Arrow(t1, t2)), tx) if tx.as_ref().is_none_or(|tx| *tx == *t1) => {
+                    result = Some(*t2);
+                    Some(*t1)
+                }
+                (Some(_), _) => {
+                    return Err(self.error_at(
+                        body,
+                        "a function of the annotated type after `let rec`",
+                        Some(
+                            "the type after `let rec f :` is the type of the whole function"
+                                .to_string(),
+                        ),
+                    ))
+                }
+            };
+            params.push((x, tx));
+            e = inner;
         }
+        Ok(recursive_function(f, params, result, e))
     }
 
-    fn aexp(l: Vec<Token>) -> Result<(exp, Vec<Token>)> {
-        let (e, l) = pexp(l)?;
-        return aexp_la(e, l);
+    /// Phrases of a program. Declarations may follow each other directly,
+    /// expressions have to be ended by `;;` or the end of the input.
+    fn program(&mut self) -> Result<Vec<phrase>> {
+        let mut phrases = vec![];
+        // an expression must not follow a declaration without `;;`
+        let mut separated = true;
+        loop {
+            while let Some(Token::SEMISEMI) = self.peek() {
+                self.bump(1);
+                separated = true;
+            }
+            if self.peek().is_none() {
+                if phrases.is_empty() {
+                    return Err(self.error("an expression or a `let` declaration", None));
+                }
+                return Ok(phrases);
+            }
+            if let Some(Token::TYPE) = self.peek() {
+                phrases.push(self.typedef()?);
+                separated = false;
+                continue;
+            }
+            let start = self.pos;
+            let e = match self.peek() {
+                Some(Token::LET) => {
+                    let binding = self.binding()?;
+                    if self.peek() != Some(&Token::IN) {
+                        phrases.push(binding.declaration());
+                        separated = false;
+                        continue;
+                    }
+                    self.separated(separated, start)?;
+                    self.bump(1);
+                    let e2 = self.seq()?;
+                    self.locate(binding.scope(e2), start)
+                }
+                _ => {
+                    self.separated(separated, start)?;
+                    self.seq()?
+                }
+            };
+            phrases.push(phrase::Exp(e));
+            if let Some(t) = self.peek() {
+                if *t != Token::SEMISEMI {
+                    return Err(self.error("`;;` or the end of the input", stray(t)));
+                }
+            }
+        }
+    }
+
+    /// Fails for the expression phrase at `start` if it directly follows a
+    /// declaration, which must be ended with `;;` first
+    fn separated(&self, separated: bool, start: usize) -> Result<()> {
+        if separated {
+            return Ok(());
+        }
+        Err(self.error_at(
+            start,
+            "`;;` or a declaration after the declaration",
+            Some("an expression after a declaration must start a new phrase with `;;`".to_string()),
+        ))
     }
 
-    fn aexp_la(e1: exp, l: Vec<Token>) -> Result<(exp, Vec<Token>)> {
-        match l.as_slice() {
-            [Token::CON(_), _n @ ..] | [Token::VAR(_), _n @ ..] | [Token::LP, _n @ ..] => {
-                let (e2, l) = pexp(l.to_vec())?;
-                return aexp_la(exp::Fapp(Box::new(e1), Box::new(e2)), l.to_vec());
+    /// `type t = C1 of t1 | ... | Cn`
+    fn typedef(&mut self) -> Result<phrase> {
+        self.bump(1);
+        let name = match self.peek() {
+            Some(Token::VAR(name)) => name.to_string(),
+            _ => return Err(self.error("a type name after `type`", None)),
+        };
+        if let "bool" | "int" | "float" | "string" | "char" | "unit" | "list" = name.as_str() {
+            let hint = format!("`{}` is a predefined type", name);
+            return Err(self.error("a new type name after `type`", Some(hint)));
+        }
+        self.bump(1);
+        self.verify(Token::EQ, "after the type name")?;
+        if let Some(Token::BAR) = self.peek() {
+            self.bump(1);
+        }
+        let mut constructors = vec![];
+        loop {
+            let c = match self.peek() {
+                Some(Token::UIDENT(c)) => c.to_string(),
+                _ => {
+                    let hint = "constructors start with an uppercase letter".to_string();
+                    return Err(self.error("a constructor", Some(hint)));
+                }
+            };
+            self.bump(1);
+            let arg = match self.peek() {
+                Some(Token::OF) => {
+                    self.bump(1);
+                    Some(self.ty()?)
+                }
+                _ => None,
+            };
+            constructors.push((c, arg));
+            match self.peek() {
+                Some(Token::BAR) => self.bump(1),
+                _ => return Ok(phrase::Type(name, constructors)),
             }
-            _ => return Ok((e1, l)),
         }
     }
-    fn pexp(l: Vec<Token>) -> Result<(exp, Vec<Token>)> {
-        match l.as_slice() {
-            [Token::CON(Constant::BCON(b)), l @ ..] => Ok((exp::Con(con::Bcon(*b)), l.to_vec())),
-            [Token::CON(Constant::ICON(n)), l @ ..] => Ok((exp::Con(con::Icon(*n)), l.to_vec())),
-            [Token::VAR(x), l @ ..] => Ok((exp::Var(x.to_string()), l.to_vec())),
-            [Token::LP, l @ ..] => {
-                let (e, l) = exp(l.to_vec())?;
-                return Ok((e, verify(Token::RP, l)?));
+
+    /// Fails if there are tokens left
+    fn end(&self) -> Result<()> {
+        match self.peek() {
+            Some(t) => Err(self.error("the end of the input", stray(t))),
+            None => Ok(()),
+        }
+    }
+
+    /// `e1 || e2`, right associative
+    fn oexp(&mut self) -> Result<exp> {
+        let e1 = self.andexp()?;
+        match self.infix(level::Or)? {
+            Some(o) => {
+                let e2 = self.oexp()?;
+                Ok(self.apply(o, e1, e2))
             }
-            e => {
-                let x: String = format!("MATCH {:?}", e);
-                Err(ParserError::PexpError(x))?
+            None => Ok(e1),
+        }
+    }
+
+    /// `e1 && e2`, right associative
+    fn andexp(&mut self) -> Result<exp> {
+        let e1 = self.cexp()?;
+        match self.infix(level::And)? {
+            Some(o) => {
+                let e2 = self.andexp()?;
+                Ok(self.apply(o, e1, e2))
             }
+            None => Ok(e1),
+        }
+    }
+
+    /// Comparisons and the operators starting with `=`, `<`, `>`, `|`, `&`
+    /// or `$`, left associative
+    fn cexp(&mut self) -> Result<exp> {
+        let mut e1 = self.lexp()?;
+        while let Some(o) = self.infix(level::Compare)? {
+            let e2 = self.lexp()?;
+            e1 = self.apply(o, e1, e2);
         }
+        Ok(e1)
+    }
+
+    /// `e1 @ e2` and the operators starting with `@` or `^`, right associative
+    fn lexp(&mut self) -> Result<exp> {
+        let e1 = self.consexp()?;
+        match self.infix(level::Concat)? {
+            Some(o) => {
+                let e2 = self.lexp()?;
+                Ok(self.apply(o, e1, e2))
+            }
+            None => Ok(e1),
+        }
+    }
+
+    /// `e1 :: e2`, right associative
+    fn consexp(&mut self) -> Result<exp> {
+        let e1 = self.sexp()?;
+        match self.infix(level::Cons)? {
+            Some(o) => {
+                let e2 = self.consexp()?;
+                Ok(self.apply(o, e1, e2))
+            }
+            None => Ok(e1),
+        }
+    }
+
+    /// `+`, `-`, `+.`, `-.` and the operators starting with `+` or `-`, left
+    /// associative
+    fn sexp(&mut self) -> Result<exp> {
+        let mut e1 = self.mexp()?;
+        while let Some(o) = self.infix(level::Add)? {
+            let e2 = self.mexp()?;
+            e1 = self.apply(o, e1, e2);
+        }
+        Ok(e1)
+    }
+
+    /// `*`, `*.`, `/.` and the operators starting with `*`, `/` or `%`, left
+    /// associative
+    fn mexp(&mut self) -> Result<exp> {
+        let mut e1 = self.powexp()?;
+        while let Some(o) = self.infix(level::Mul)? {
+            let e2 = self.powexp()?;
+            e1 = self.apply(o, e1, e2);
+        }
+        Ok(e1)
+    }
+
+    /// The operators starting with `**`, right associative
+    fn powexp(&mut self) -> Result<exp> {
+        let e1 = self.uexp()?;
+        match self.infix(level::Power)? {
+            Some(o) => {
+                let e2 = self.powexp()?;
+                Ok(self.apply(o, e1, e2))
+            }
+            None => Ok(e1),
+        }
+    }
+
+    /// `-e` and `-.e`, binding tighter than `*` but looser than application.
+    /// Negated number literals are constants.
+    fn uexp(&mut self) -> Result<exp> {
+        let start = self.pos;
+        let o = match self.peek() {
+            Some(Token::SUB) => unary::Neg,
+            Some(Token::FSUB) => unary::FNeg,
+            _ => return self.aexp(),
+        };
+        self.bump(1);
+        let e = match (o, self.peek()) {
+            (unary::Neg, Some(Token::CON(Constant::ICON(n)))) => {
+                exp::Con(con::Icon(n.wrapping_neg()))
+            }
+            (_, Some(Token::CON(Constant::FCON(x)))) => exp::Con(con::Fcon(-x)),
+            (o, _) => {
+                let e = self.uexp()?;
+                return Ok(self.locate(exp::Uapp(o, Box::new(e)), start));
+            }
+        };
+        self.bump(1);
+        Ok(self.locate(e, start))
+    }
+
+    /// Function application and constructors applied to their argument
+    fn aexp(&mut self) -> Result<exp> {
+        let start = self.pos;
+        let mut e1 = match self.rest() {
+            [Token::UIDENT(c), t, ..] if starts_argument(t) => {
+                self.bump(1);
+                let e = exp::Constr(c.to_string(), Some(Box::new(self.pexp()?)));
+                self.locate(e, start)
+            }
+            _ => self.pexp()?,
+        };
+        while self.peek().is_some_and(starts_argument) {
+            let e2 = self.pexp()?;
+            let (from, to) = (e1.span(), e2.span());
+            e1 = self.locate_between(exp::Fapp(Box::new(e1), Box::new(e2)), from, to);
+        }
+        Ok(e1)
+    }
+
+    fn pexp(&mut self) -> Result<exp> {
+        let start = self.pos;
+        let e = match self.peek() {
+            Some(Token::CON(Constant::BCON(b))) => exp::Con(con::Bcon(*b)),
+            Some(Token::CON(Constant::ICON(n))) => exp::Con(con::Icon(*n)),
+            Some(Token::CON(Constant::FCON(x))) => exp::Con(con::Fcon(*x)),
+            Some(Token::CON(Constant::SCON(s))) => exp::Con(con::Scon(s.to_string())),
+            Some(Token::CON(Constant::CCON(c))) => exp::Con(con::Ccon(*c)),
+            Some(Token::VAR(x)) => exp::Var(x.to_string()),
+            Some(Token::UIDENT(c)) => exp::Constr(c.to_string(), None),
+            Some(t @ (Token::PREFIXOP(_) | Token::BANG)) => {
+                let op = prefix_operator(t).expect("prefix operator").to_string();
+                self.bump(1);
+                let e = Box::new(self.pexp()?);
+                let e = match op.as_str() {
+                    "~-" => exp::Uapp(unary::Neg, e),
+                    "~-." => exp::Uapp(unary::FNeg, e),
+                    _ => exp::Fapp(Box::new(exp::Var(op)), e),
+                };
+                return Ok(self.locate(e, start));
+            }
+            Some(Token::LP)
+                if self.rest().get(2) == Some(&Token::RP)
+                    && self.rest().get(1).is_some_and(is_operator) =>
+            {
+                let e = section(&self.rest()[1]).expect("operator");
+                self.bump(3);
+                return Ok(self.locate(e, start));
+            }
+            Some(Token::LP) if self.rest().get(1) == Some(&Token::RP) => {
+                self.bump(2);
+                return Ok(self.locate(exp::Con(con::Ucon), start));
+            }
+            Some(Token::BEGIN) => {
+                self.bump(1);
+                let e = match self.peek() {
+                    Some(Token::END) => self.locate(exp::Con(con::Ucon), start),
+                    _ => self.seq()?,
+                };
+                if self.peek() != Some(&Token::END) {
+                    return Err(self.error("`end`", Some(self.unclosed(start, "begin"))));
+                }
+                self.bump(1);
+                return Ok(e);
+            }
+            Some(Token::LP) => {
+                self.bump(1);
+                let e = self.seq()?;
+                if let Some(Token::COL) = self.peek() {
+                    self.bump(1);
+                    let t = self.ty()?;
+                    self.close(start)?;
+                    return Ok(self.locate(exp::Annot(Box::new(e), t), start));
+                }
+                self.close(start)?;
+                return Ok(e);
+            }
+            Some(Token::LB) => {
+                self.bump(1);
+                let mut es = vec![];
+                while self.peek() != Some(&Token::RB) {
+                    es.push(self.exp()?);
+                    match self.peek() {
+                        Some(Token::SEMI) => self.bump(1),
+                        Some(Token::RB) => {}
+                        _ => {
+                            let hint = self.unclosed(start, "[");
+                            return Err(self.error("`;` or `]` in the list", Some(hint)));
+                        }
+                    }
+                }
+                exp::List(es)
+            }
+            found => return Err(self.error("an expression", found.and_then(stray))),
+        };
+        self.bump(1);
+        Ok(self.locate(e, start))
     }
 }
@@ -2,84 +2,626 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use crate::error::{Result, TypeCheckError};
-use crate::parse::{con, exp, operator, ty};
-use std::collections::BTreeMap;
-
-fn check_operator(o: operator, t1: ty, t2: ty) -> Result<ty> {
-    match (o, t1, t2) {
-        (operator::Add, ty::Int, ty::Int)
-        | (operator::Sub, ty::Int, ty::Int)
-        | (operator::Mul, ty::Int, ty::Int) => Ok(ty::Int),
-        (operator::Leq, ty::Int, ty::Int) => Ok(ty::Bool),
-        (_, _, _) => Err(TypeCheckError::ArgumentError)?,
+use crate::error::{Result, TypeCheckError, Warning};
+use crate::parse::{con, exp, operator, pattern, phrase, ty, unary};
+use std::collections::{BTreeMap, BTreeSet};
+use std::rc::Rc;
+
+/// Types of the predefined functions, used when a variable is not bound
+fn builtin_type(x: &str) -> Option<ty> {
+    match x {
+        "float_of_int" => Some(ty::Arrow(Box::new(ty::Int), Box::new(ty::Float))),
+        "int_of_float" => Some(ty::Arrow(Box::new(ty::Float), Box::new(ty::Int))),
+        "ignore" => Some(ty::Forall(
+            vec![0],
+            Box::new(ty::Arrow(Box::new(ty::Var(0)), Box::new(ty::Unit))),
+        )),
+        "fst" | "snd" => {
+            let pair = ty::Tuple(vec![ty::Var(0), ty::Var(1)]);
+            let t = ty::Var(if x == "fst" { 0 } else { 1 });
+            Some(ty::Forall(
+                vec![0, 1],
+                Box::new(ty::Arrow(Box::new(pair), Box::new(t))),
+            ))
+        }
+        _ => None,
+    }
+}
+
+fn con_type(c: &con) -> ty {
+    match c {
+        con::Bcon(_b) => ty::Bool,
+        con::Icon(_n) => ty::Int,
+        con::Fcon(_x) => ty::Float,
+        con::Scon(_s) => ty::String,
+        con::Ccon(_c) => ty::Char,
+        con::Ucon => ty::Unit,
     }
 }
 
-fn check_fun(t1: ty, t2: ty) -> Result<ty> {
-    match t1 {
-        ty::Arrow(t1_, t2_) => {
-            if *t1_ == t2 {
-                return Ok(*t2_);
-            } else {
-                Err(TypeCheckError::WrongArgument)?
+/// Free type variables of `t`
+fn free_vars(t: &ty, vars: &mut BTreeSet<usize>) {
+    match t {
+        ty::Var(n) => {
+            vars.insert(*n);
+        }
+        ty::Arrow(t1, t2) => {
+            free_vars(t1, vars);
+            free_vars(t2, vars);
+        }
+        ty::Tuple(ts) => {
+            for t in ts {
+                free_vars(t, vars);
             }
         }
-        _ => Err(TypeCheckError::MissingFunction)?,
+        ty::List(t) => free_vars(t, vars),
+        ty::Forall(bound, t) => {
+            let mut inner = BTreeSet::new();
+            free_vars(t, &mut inner);
+            vars.extend(inner.into_iter().filter(|n| !bound.contains(n)));
+        }
+        ty::Bool | ty::Int | ty::Float | ty::String | ty::Char | ty::Unit | ty::Named(_) => {}
     }
 }
 
-pub fn type_check(env: &mut BTreeMap<String, ty>, e: exp) -> Result<ty> {
-    match e {
-        exp::Var(x) => match env.get(&x) {
-            Some(t) => Ok(t.clone()),
-            None => Err(TypeCheckError::UnboundVariable(x))?,
-        },
-        exp::Con(con::Bcon(_b)) => Ok(ty::Bool),
-        exp::Con(con::Icon(_n)) => Ok(ty::Int),
-        exp::Oapp(o, e1, e2) => Ok(check_operator(
-            o,
-            type_check(env, *e1)?,
-            type_check(env, *e2)?,
-        )?),
-        exp::Fapp(e1, e2) => Ok(check_fun(type_check(env, *e1)?, type_check(env, *e2)?)?),
-        exp::If(e1, e2, e3) => match (
-            type_check(env, *e1)?,
-            type_check(env, *e2)?,
-            type_check(env, *e3)?,
-        ) {
-            (ty::Bool, t2, t3) => {
-                if t2 == t3 {
-                    return Ok(t2);
-                } else {
-                    Err(TypeCheckError::UnequalIfTypes)?
+/// Argument and result type of the constructor `c`, which is applied to an
+/// argument if `applied`. Constructors are bound in the environment like
+/// variables, with an uppercase name.
+fn constructor(env: &BTreeMap<String, ty>, c: &str, applied: bool) -> Result<(Option<ty>, ty)> {
+    match (env.get(c), applied) {
+        (None, _) => Err(TypeCheckError::UnboundConstructor(c.to_string()))?,
+        (Some(ty::Arrow(targ, t)), true) => Ok((Some(*targ.clone()), *t.clone())),
+        (Some(t @ ty::Named(_)), false) => Ok((None, t.clone())),
+        (Some(_), _) => Err(TypeCheckError::ConstructorArity(c.to_string()))?,
+    }
+}
+
+/// Names of the types declared by `type`, the types of the constructors in
+/// `env`
+fn declared_types(env: &BTreeMap<String, ty>) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    for (c, t) in env {
+        if !c.starts_with(char::is_uppercase) {
+            continue;
+        }
+        let t = match t {
+            ty::Arrow(_, t) => t,
+            t => t,
+        };
+        if let ty::Named(name) = t {
+            names.insert(name.to_string());
+        }
+    }
+    names
+}
+
+/// Fails if `t` refers to a type that is not `declared`
+fn check_declared(t: &ty, declared: &BTreeSet<String>) -> Result<()> {
+    match t {
+        ty::Named(name) if !declared.contains(name) => {
+            Err(TypeCheckError::UnboundType(name.to_string()))?
+        }
+        ty::Arrow(t1, t2) => {
+            check_declared(t1, declared)?;
+            check_declared(t2, declared)
+        }
+        ty::Tuple(ts) => ts.iter().try_for_each(|t| check_declared(t, declared)),
+        ty::List(t) | ty::Forall(_, t) => check_declared(t, declared),
+        _ => Ok(()),
+    }
+}
+
+/// Replaces the variables of `t` according to `s`
+fn substitute(t: &ty, s: &BTreeMap<usize, ty>) -> ty {
+    match t {
+        ty::Var(n) => s.get(n).cloned().unwrap_or(ty::Var(*n)),
+        ty::Arrow(t1, t2) => ty::Arrow(Box::new(substitute(t1, s)), Box::new(substitute(t2, s))),
+        ty::Tuple(ts) => ty::Tuple(ts.iter().map(|t| substitute(t, s)).collect()),
+        ty::List(t) => ty::List(Box::new(substitute(t, s))),
+        ty::Forall(bound, t) => {
+            let mut s = s.clone();
+            for n in bound {
+                s.remove(n);
+            }
+            ty::Forall(bound.clone(), Box::new(substitute(t, &s)))
+        }
+        t => t.clone(),
+    }
+}
+
+/// Hindley-Milner type inference: type variables are solved by unification,
+/// and let-bound variables get polymorphic types.
+struct Inference {
+    /// solutions of the type variables, `None` while unsolved
+    solutions: Vec<Option<ty>>,
+    warnings: Vec<Warning>,
+}
+
+impl Inference {
+    fn fresh(&mut self) -> ty {
+        self.solutions.push(None);
+        ty::Var(self.solutions.len() - 1)
+    }
+
+    /// `t` with all solved variables replaced by their solutions
+    fn resolve(&self, t: &ty) -> ty {
+        self.resolve_free(t, &[])
+    }
+
+    /// Like `resolve`, but leaves the `bound` variables of a polymorphic
+    /// type alone (they may be from an earlier inference)
+    fn resolve_free(&self, t: &ty, bound: &[usize]) -> ty {
+        match t {
+            ty::Var(n) if bound.contains(n) => ty::Var(*n),
+            ty::Var(n) => match self.solutions.get(*n) {
+                Some(Some(t)) => self.resolve(t),
+                _ => ty::Var(*n),
+            },
+            ty::Arrow(t1, t2) => ty::Arrow(
+                Box::new(self.resolve_free(t1, bound)),
+                Box::new(self.resolve_free(t2, bound)),
+            ),
+            ty::Tuple(ts) => ty::Tuple(ts.iter().map(|t| self.resolve_free(t, bound)).collect()),
+            ty::List(t) => ty::List(Box::new(self.resolve_free(t, bound))),
+            ty::Forall(vars, t) => {
+                let bound = [bound, vars].concat();
+                ty::Forall(vars.clone(), Box::new(self.resolve_free(t, &bound)))
+            }
+            t => t.clone(),
+        }
+    }
+
+    /// Makes `t1` and `t2` equal, failing with `err` if they cannot be
+    fn unify(&mut self, t1: &ty, t2: &ty, err: TypeCheckError) -> Result<()> {
+        match self.unifies(t1, t2)? {
+            true => Ok(()),
+            false => Err(err)?,
+        }
+    }
+
+    fn unifies(&mut self, t1: &ty, t2: &ty) -> Result<bool> {
+        match (self.resolve(t1), self.resolve(t2)) {
+            (ty::Var(n), ty::Var(m)) if n == m => Ok(true),
+            (ty::Var(n), t) | (t, ty::Var(n)) => {
+                let mut vars = BTreeSet::new();
+                free_vars(&t, &mut vars);
+                if vars.contains(&n) {
+                    Err(TypeCheckError::InfiniteType)?
+                }
+                self.solutions[n] = Some(t);
+                Ok(true)
+            }
+            (ty::Arrow(a1, b1), ty::Arrow(a2, b2)) => {
+                Ok(self.unifies(&a1, &a2)? && self.unifies(&b1, &b2)?)
+            }
+            (ty::Tuple(ts1), ty::Tuple(ts2)) if ts1.len() == ts2.len() => {
+                for (t1, t2) in ts1.iter().zip(ts2.iter()) {
+                    if !self.unifies(t1, t2)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            (ty::List(t1), ty::List(t2)) => self.unifies(&t1, &t2),
+            (t1, t2) => Ok(t1 == t2),
+        }
+    }
+
+    /// Copy of the polymorphic type `t` with fresh variables
+    fn instantiate(&mut self, t: &ty) -> ty {
+        match t {
+            ty::Forall(bound, t) => {
+                let s = bound.iter().map(|n| (*n, self.fresh())).collect();
+                substitute(t, &s)
+            }
+            t => t.clone(),
+        }
+    }
+
+    /// Polymorphic type of `t`, quantified over the variables not used in `env`
+    fn generalize(&self, env: &BTreeMap<String, ty>, t: &ty) -> ty {
+        let t = self.resolve(t);
+        let mut vars = BTreeSet::new();
+        free_vars(&t, &mut vars);
+        let mut used = BTreeSet::new();
+        for t in env.values() {
+            free_vars(&self.resolve(t), &mut used);
+        }
+        let bound: Vec<usize> = vars.difference(&used).copied().collect();
+        match bound.is_empty() {
+            true => t,
+            false => ty::Forall(bound, Box::new(t)),
+        }
+    }
+
+    fn check_operator(&mut self, o: operator, t1: ty, t2: ty) -> Result<ty> {
+        let err = || TypeCheckError::ArgumentError;
+        match o {
+            operator::Add | operator::Sub | operator::Mul | operator::Div => {
+                self.unify(&t1, &ty::Int, err())?;
+                self.unify(&t2, &ty::Int, err())?;
+                Ok(ty::Int)
+            }
+            operator::FAdd | operator::FSub | operator::FMul | operator::FDiv => {
+                self.unify(&t1, &ty::Float, err())?;
+                self.unify(&t2, &ty::Float, err())?;
+                Ok(ty::Float)
+            }
+            operator::Concat => {
+                self.unify(&t1, &ty::String, err())?;
+                self.unify(&t2, &ty::String, err())?;
+                Ok(ty::String)
+            }
+            operator::Eq
+            | operator::Neq
+            | operator::Lt
+            | operator::Gt
+            | operator::Leq
+            | operator::Geq => {
+                self.unify(&t1, &t2, err())?;
+                match self.resolve(&t1) {
+                    // functions cannot be compared
+                    ty::Arrow(_, _) => Err(err())?,
+                    _ => Ok(ty::Bool),
+                }
+            }
+            operator::Cons => {
+                let t = ty::List(Box::new(t1));
+                self.unify(&t2, &t, err())?;
+                Ok(t)
+            }
+            operator::Append => {
+                let t = ty::List(Box::new(self.fresh()));
+                self.unify(&t1, &t, err())?;
+                self.unify(&t2, &t, err())?;
+                Ok(t)
+            }
+        }
+    }
+
+    fn check_fun(&mut self, t1: ty, t2: ty) -> Result<ty> {
+        match self.resolve(&t1) {
+            ty::Arrow(t1_, t2_) => {
+                self.unify(&t1_, &t2, TypeCheckError::WrongArgument)?;
+                Ok(*t2_)
+            }
+            ty::Var(_) => {
+                let t = self.fresh();
+                let f = ty::Arrow(Box::new(t2), Box::new(t.clone()));
+                self.unify(&t1, &f, TypeCheckError::MissingFunction)?;
+                Ok(t)
+            }
+            _ => Err(TypeCheckError::MissingFunction)?,
+        }
+    }
+
+    fn exp(&mut self, env: &mut BTreeMap<String, ty>, e: exp) -> Result<ty> {
+        match e {
+            exp::Var(x) => match env.get(&x).cloned().or_else(|| builtin_type(&x)) {
+                Some(t) => Ok(self.instantiate(&t)),
+                None => Err(TypeCheckError::UnboundVariable(x))?,
+            },
+            exp::Con(c) => Ok(con_type(&c)),
+            exp::Oapp(o, e1, e2) => {
+                let t1 = self.exp(env, *e1)?;
+                let t2 = self.exp(env, *e2)?;
+                self.check_operator(o, t1, t2)
+            }
+            exp::Uapp(o, e) => {
+                let t = self.exp(env, *e)?;
+                let result = match o {
+                    unary::Neg => ty::Int,
+                    unary::FNeg => ty::Float,
+                };
+                self.unify(&t, &result, TypeCheckError::ArgumentError)?;
+                Ok(result)
+            }
+            exp::Fapp(e1, e2) => {
+                let t1 = self.exp(env, *e1)?;
+                let t2 = self.exp(env, *e2)?;
+                self.check_fun(t1, t2)
+            }
+            exp::If(e1, e2, e3) => {
+                let t1 = self.exp(env, *e1)?;
+                let t2 = self.exp(env, *e2)?;
+                let t3 = self.exp(env, *e3)?;
+                let x = self.resolve(&t1).to_string();
+                self.unify(&t1, &ty::Bool, TypeCheckError::WrongIfType(x))?;
+                self.unify(&t2, &t3, TypeCheckError::UnequalIfTypes)?;
+                Ok(t2)
+            }
+            exp::Lam(x, e) => {
+                let t = self.fresh();
+                self.lambda(env, x, t, Rc::unwrap_or_clone(e))
+            }
+            exp::Lamty(x, t, e) => {
+                check_declared(&t, &declared_types(env))?;
+                self.lambda(env, x, t, Rc::unwrap_or_clone(e))
+            }
+            exp::Let(x, e1, e2) => {
+                let t = self.exp(env, *e1)?;
+                let t = self.generalize(env, &t);
+                let mut new_env = env.clone();
+                new_env.insert(x, t);
+                self.exp(&mut new_env, *e2)
+            }
+            exp::Loc(span, e) => self.exp(env, *e).map_err(|err| err.at(span)),
+            exp::Letrec(f, x, e1, e2) => {
+                let (t1, t2) = (self.fresh(), self.fresh());
+                self.letrec(env, vec![(f, x, t1, t2, Rc::unwrap_or_clone(e1))], *e2)
+            }
+            exp::Letrecty(f, x, t1, t2, e1, e2) => {
+                let declared = declared_types(env);
+                check_declared(&t1, &declared)?;
+                check_declared(&t2, &declared)?;
+                self.letrec(env, vec![(f, x, t1, t2, Rc::unwrap_or_clone(e1))], *e2)
+            }
+            exp::Letrecand(functions, e2) => {
+                let mut typed = vec![];
+                for (f, x, declared, e1) in functions {
+                    let (t1, t2) = match declared {
+                        Some((t1, t2)) => {
+                            let declared = declared_types(env);
+                            check_declared(&t1, &declared)?;
+                            check_declared(&t2, &declared)?;
+                            (t1, t2)
+                        }
+                        None => (self.fresh(), self.fresh()),
+                    };
+                    typed.push((f, x, t1, t2, Rc::unwrap_or_clone(e1)));
+                }
+                self.letrec(env, typed, *e2)
+            }
+            exp::Tuple(es) => {
+                let mut ts = vec![];
+                for e in es {
+                    ts.push(self.exp(env, e)?);
+                }
+                Ok(ty::Tuple(ts))
+            }
+            exp::Match(e, cases) => {
+                let t = self.exp(env, *e)?;
+                let result = self.fresh();
+                for (p, e) in cases {
+                    let mut bindings = vec![];
+                    let tp = self.pattern(env, &p, &mut bindings)?;
+                    let err = TypeCheckError::PatternMismatch(self.resolve(&t).to_string());
+                    self.unify(&tp, &t, err)?;
+                    // variables bound by a case are not polymorphic
+                    let mut new_env = env.clone();
+                    new_env.extend(bindings);
+                    let te = self.exp(&mut new_env, e)?;
+                    self.unify(&te, &result, TypeCheckError::UnequalMatchTypes)?;
+                }
+                Ok(result)
+            }
+            exp::List(es) => {
+                let t = self.fresh();
+                for e in es {
+                    let te = self.exp(env, e)?;
+                    self.unify(&te, &t, TypeCheckError::UnequalListTypes)?;
+                }
+                Ok(ty::List(Box::new(t)))
+            }
+            exp::Letpat(p, e1, e2) => {
+                let t = self.exp(env, *e1)?;
+                let mut new_env = env.clone();
+                self.bind(&mut new_env, &p, &t)?;
+                self.exp(&mut new_env, *e2)
+            }
+            exp::Seq(e1, e2) => {
+                let span = e1.span();
+                let t1 = self.exp(env, *e1)?;
+                let t1 = self.resolve(&t1);
+                if !matches!(t1, ty::Unit | ty::Var(_)) {
+                    let found = t1.to_string();
+                    self.warnings
+                        .push(Warning::NonUnitStatement { found, span });
+                }
+                self.exp(env, *e2)
+            }
+            exp::Constr(c, arg) => {
+                let (targ, t) = constructor(env, &c, arg.is_some())?;
+                if let (Some(targ), Some(e)) = (targ, arg) {
+                    let te = self.exp(env, *e)?;
+                    self.unify(&te, &targ, TypeCheckError::ConstructorArgument(c))?;
+                }
+                Ok(t)
+            }
+            exp::Annot(e, t) => {
+                check_declared(&t, &declared_types(env))?;
+                let te = self.exp(env, *e)?;
+                let err = TypeCheckError::AnnotationMismatch(
+                    self.resolve(&te).to_string(),
+                    t.to_string(),
+                );
+                self.unify(&te, &t, err)?;
+                Ok(t)
+            }
+        }
+    }
+
+    /// Type of the values matching `p`, adding its variables to `bindings`
+    fn pattern(
+        &mut self,
+        env: &BTreeMap<String, ty>,
+        p: &pattern,
+        bindings: &mut Vec<(String, ty)>,
+    ) -> Result<ty> {
+        match p {
+            pattern::Var(x) => {
+                if bindings.iter().any(|(y, _)| y == x) {
+                    Err(TypeCheckError::RepeatedVariable(x.to_string()))?
+                }
+                let t = self.fresh();
+                bindings.push((x.to_string(), t.clone()));
+                Ok(t)
+            }
+            pattern::Wildcard => Ok(self.fresh()),
+            pattern::Con(c) => Ok(con_type(c)),
+            pattern::Tuple(ps) => {
+                let mut ts = vec![];
+                for p in ps {
+                    ts.push(self.pattern(env, p, bindings)?);
+                }
+                Ok(ty::Tuple(ts))
+            }
+            pattern::List(ps) => {
+                let t = self.fresh();
+                for p in ps {
+                    let tp = self.pattern(env, p, bindings)?;
+                    self.unify(&tp, &t, TypeCheckError::UnequalListTypes)?;
                 }
+                Ok(ty::List(Box::new(t)))
+            }
+            pattern::Cons(p1, p2) => {
+                let t = ty::List(Box::new(self.pattern(env, p1, bindings)?));
+                let t2 = self.pattern(env, p2, bindings)?;
+                let err = TypeCheckError::PatternMismatch(self.resolve(&t).to_string());
+                self.unify(&t2, &t, err)?;
+                Ok(t)
             }
-            (x, _, _) => {
-                let x: String = format!("{:?}", x);
-                Err(TypeCheckError::WrongIfType(x))?
+            pattern::Constr(c, arg) => {
+                let (targ, t) = constructor(env, c, arg.is_some())?;
+                if let (Some(targ), Some(p)) = (targ, arg) {
+                    let tp = self.pattern(env, p, bindings)?;
+                    let err = TypeCheckError::PatternMismatch(targ.to_string());
+                    self.unify(&tp, &targ, err)?;
+                }
+                Ok(t)
             }
-        },
-        exp::Lam(_, _) => Err(TypeCheckError::MissingFunctionType)?,
-        exp::Lamty(x, t, e) => {
-            env.insert(x, t.clone());
-            Ok(ty::Arrow(Box::new(t), Box::new(type_check(env, *e)?)))
         }
-        exp::Let(x, e1, e2) => {
-            let t = type_check(env, *e1)?;
+    }
+
+    /// Adds the variables of `p`, matched against a value of type `t`, to `env`
+    fn bind(&mut self, env: &mut BTreeMap<String, ty>, p: &pattern, t: &ty) -> Result<()> {
+        let mut bindings = vec![];
+        let tp = self.pattern(env, p, &mut bindings)?;
+        self.unify(
+            &tp,
+            t,
+            TypeCheckError::PatternMismatch(self.resolve(t).to_string()),
+        )?;
+        for (x, t) in bindings {
+            let t = self.generalize(env, &t);
             env.insert(x, t);
-            type_check(env, *e2)
         }
-        exp::Letrec(_f, _x, _e1, _e2) => Err(TypeCheckError::MissingType)?,
-        exp::Letrecty(f, x, t1, t2, e1, e2) => {
-            env.insert(f, ty::Arrow(Box::new(t1.clone()), Box::new(t2.clone())));
-            let mut new_env = env.clone();
-            new_env.insert(x, t1);
-            if type_check(&mut new_env, *e1)? == t2 {
-                return type_check(env, *e2);
-            } else {
-                Err(TypeCheckError::NoTypeMatch)?
+        Ok(())
+    }
+
+    fn lambda(&mut self, env: &BTreeMap<String, ty>, x: String, t: ty, e: exp) -> Result<ty> {
+        let mut new_env = env.clone();
+        new_env.insert(x, t.clone());
+        let t2 = self.exp(&mut new_env, e)?;
+        Ok(ty::Arrow(Box::new(t), Box::new(t2)))
+    }
+
+    /// `let rec f x = e1 and ... in e2` for functions `f: t1 -> t2`, which
+    /// are all in scope in every body
+    fn letrec(
+        &mut self,
+        env: &mut BTreeMap<String, ty>,
+        functions: Vec<(String, String, ty, ty, exp)>,
+        e2: exp,
+    ) -> Result<ty> {
+        let mut new_env = env.clone();
+        for (f, _, t1, t2, _) in &functions {
+            let tf = ty::Arrow(Box::new(t1.clone()), Box::new(t2.clone()));
+            new_env.insert(f.clone(), tf);
+        }
+        let mut types = vec![];
+        for (f, x, t1, t2, e1) in functions {
+            let mut body_env = new_env.clone();
+            body_env.insert(x, t1.clone());
+            let t = self.exp(&mut body_env, e1)?;
+            self.unify(&t, &t2, TypeCheckError::NoTypeMatch)?;
+            types.push((f, ty::Arrow(Box::new(t1), Box::new(t2))));
+        }
+        let types: Vec<_> = types
+            .into_iter()
+            .map(|(f, tf)| (f, self.generalize(env, &tf)))
+            .collect();
+        let mut env = env.clone();
+        env.extend(types);
+        self.exp(&mut env, e2)
+    }
+}
+
+/// Infers the type of `e`. The variables bound inside of `e` are only visible
+/// in their scope, they are not added to `env`.
+pub fn type_check(env: &mut BTreeMap<String, ty>, e: exp) -> Result<ty> {
+    infer(env, &mut vec![], |inference, env| inference.exp(env, e))
+}
+
+/// Infers the type of a phrase of a program, adding declared variables to `env`
+/// and found problems to `warnings`
+pub fn type_check_phrase(
+    env: &mut BTreeMap<String, ty>,
+    p: phrase,
+    warnings: &mut Vec<Warning>,
+) -> Result<ty> {
+    match p {
+        phrase::Let(p, e) => infer(env, warnings, |inference, env| {
+            let t = inference.exp(env, e)?;
+            inference.bind(env, &p, &t)?;
+            Ok(t)
+        }),
+        phrase::Exp(e) => infer(env, warnings, |inference, env| inference.exp(env, e)),
+        phrase::Type(name, constructors) => {
+            // types and constructors cannot be redeclared, so values of an
+            // earlier `type t` are never mistaken for values of a later one
+            let mut declared = declared_types(env);
+            if !declared.insert(name.clone()) {
+                Err(TypeCheckError::RedeclaredType(name.clone()))?
+            }
+            let mut names = BTreeSet::new();
+            for (c, _) in &constructors {
+                if env.contains_key(c) || !names.insert(c) {
+                    Err(TypeCheckError::RedeclaredConstructor(c.to_string()))?
+                }
+            }
+            for (_, arg) in &constructors {
+                if let Some(t) = arg {
+                    check_declared(t, &declared)?;
+                }
+            }
+            for (c, arg) in constructors {
+                let t = ty::Named(name.clone());
+                let t = match arg {
+                    Some(targ) => ty::Arrow(Box::new(targ), Box::new(t)),
+                    None => t,
+                };
+                env.insert(c, t);
             }
+            Ok(ty::Unit)
         }
     }
 }
+
+fn infer<F>(env: &mut BTreeMap<String, ty>, warnings: &mut Vec<Warning>, f: F) -> Result<ty>
+where
+    F: FnOnce(&mut Inference, &mut BTreeMap<String, ty>) -> Result<ty>,
+{
+    let mut inference = Inference {
+        solutions: vec![],
+        warnings: vec![],
+    };
+    let result = f(&mut inference, env);
+    warnings.append(&mut inference.warnings);
+    // nothing outside of this inference refers to its variables, so all of
+    // them can be generalized
+    for t in env.values_mut() {
+        *t = generalize(&inference.resolve(t));
+    }
+    result.map(|t| inference.resolve(&t))
+}
+
+/// Polymorphic type quantified over all variables of `t`
+fn generalize(t: &ty) -> ty {
+    let mut vars = BTreeSet::new();
+    free_vars(t, &mut vars);
+    match vars.is_empty() {
+        true => t.clone(),
+        false => ty::Forall(vars.into_iter().collect(), Box::new(t.clone())),
+    }
+}
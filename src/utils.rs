@@ -3,7 +3,7 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use crate::error::{Result, UtilsError};
-use std::iter::{Iterator, Peekable};
+use std::iter::Iterator;
 
 fn _getsubchar_generic<I, T>(iterator: &mut I, n: usize) -> Result<Vec<T>>
 where
@@ -18,22 +18,5 @@ where
             Err(UtilsError::OutOfBounds)?
         });
     }
-    return Ok(s);
-}
-
-pub fn getsubchar<I>(iterator: &mut Peekable<I>, n: usize) -> Result<String>
-where
-    I: Iterator<Item = char> + Clone,
-{
-    let mut s = String::new();
-    let mut citerator = iterator.clone();
-
-    for _ in 0..n {
-        s.push(if let Some(n) = citerator.next() {
-            n.clone()
-        } else {
-            Err(UtilsError::OutOfBounds)?
-        });
-    }
-    return Ok(s);
+    Ok(s)
 }